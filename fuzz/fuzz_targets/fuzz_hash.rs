@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/crypto.rs"]
+mod crypto;
+
+use crypto::{Method, PasswordHandler};
+
+fuzz_target!(|password: &str| {
+    let handler = PasswordHandler::new(10, "Pepper".into(), Vec::new());
+    for method in [
+        Method::Hash,
+        Method::Salt,
+        Method::Pepper,
+        Method::SaltPepper,
+    ] {
+        let hash = handler.hash(password, method);
+        assert!(handler.is_hash_of(password, &hash, method));
+    }
+});
@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/crypto.rs"]
+mod crypto;
+
+use crypto::{Method, PasswordHandler};
+
+const PASSWORD_LENGTH: usize = 32;
+
+fuzz_target!(|data: &[u8]| {
+    let (password, hash) = data.split_at(data.len().min(PASSWORD_LENGTH));
+    let password = String::from_utf8_lossy(password);
+    let hash = String::from_utf8_lossy(hash);
+
+    let handler = PasswordHandler::new(10, "Pepper".into(), vec!["Salt".into()]);
+    let _ = handler.is_hash_of(&password, &hash, Method::SaltPepper);
+    let _ = handler.verify(&password, &hash, Method::SaltPepper);
+    let _ = handler.needs_rehash(&hash);
+});
@@ -0,0 +1,2 @@
+ALTER TABLE accounts
+    ADD COLUMN IF NOT EXISTS locked_by_admin BOOLEAN NOT NULL DEFAULT FALSE;
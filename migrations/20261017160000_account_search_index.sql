@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS accounts_search_idx
+    ON accounts USING GIN (to_tsvector('english', name || ' ' || email));
@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS audit_log_user_id_created_at_idx
+    ON audit_log (user_id, created_at);
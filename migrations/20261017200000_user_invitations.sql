@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS user_invitations(
+    id BIGSERIAL PRIMARY KEY,
+    token_hash BYTEA NOT NULL UNIQUE,
+    email TEXT NOT NULL,
+    role TEXT NOT NULL,
+    invited_by BIGINT,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used BOOL NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS user_invitations_pending_idx
+    ON user_invitations (expires_at)
+    WHERE NOT used;
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS passkey_user_accounts(
+    passkey_user_id UUID PRIMARY KEY REFERENCES passkey_users(id) ON DELETE CASCADE,
+    account_id BIGINT NOT NULL UNIQUE REFERENCES accounts(id) ON DELETE CASCADE,
+    linked_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+UPDATE accounts
+SET role = 'user'
+WHERE btrim(role) = '';
@@ -0,0 +1,5 @@
+ALTER TABLE accounts
+    ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+ALTER TABLE passkey_users
+    ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
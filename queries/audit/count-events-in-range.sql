@@ -0,0 +1,7 @@
+SELECT
+    COUNT(*) AS "count!"
+FROM
+    audit_log
+WHERE
+    created_at >= $1
+    AND created_at <= $2;
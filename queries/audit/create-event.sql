@@ -0,0 +1,13 @@
+INSERT INTO audit_log(
+    user_id,
+    action,
+    ip_address,
+    user_agent,
+    metadata
+) VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5
+);
@@ -0,0 +1,23 @@
+SELECT
+    audit_log.id,
+    audit_log.user_id,
+    CASE
+        WHEN audit_log.user_id IS NULL THEN NULL
+        WHEN accounts.id IS NULL OR accounts.deleted_at IS NOT NULL THEN '[DELETED USER]'
+        ELSE accounts.name
+    END AS user_name,
+    audit_log.action,
+    audit_log.ip_address AS "ip_address: IpAddr",
+    audit_log.user_agent,
+    audit_log.metadata,
+    audit_log.created_at
+FROM
+    audit_log
+    LEFT JOIN accounts ON accounts.id = audit_log.user_id
+WHERE
+    audit_log.created_at >= $1
+    AND audit_log.created_at <= $2
+    AND audit_log.id > $3
+ORDER BY
+    audit_log.id
+LIMIT $4
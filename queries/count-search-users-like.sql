@@ -0,0 +1,7 @@
+SELECT
+    COUNT(*) AS "count!"
+FROM
+    accounts
+WHERE
+    (name ILIKE '%' || $1 || '%' OR email ILIKE '%' || $1 || '%')
+    AND deleted_at IS NULL;
@@ -0,0 +1,7 @@
+SELECT
+    COUNT(*) AS "count!"
+FROM
+    accounts
+WHERE
+    to_tsvector('english', name || ' ' || email) @@ plainto_tsquery('english', $1)
+    AND deleted_at IS NULL;
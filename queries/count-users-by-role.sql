@@ -0,0 +1,7 @@
+SELECT
+    role,
+    COUNT(*) AS "count!"
+FROM
+    accounts
+GROUP BY
+    role;
@@ -0,0 +1,3 @@
+SELECT
+    (SELECT COUNT(*) FROM accounts WHERE created_at >= $1 AND created_at < $2)
+    + (SELECT COUNT(*) FROM passkey_users WHERE created_at >= $1 AND created_at < $2) AS "count!";
@@ -14,4 +14,6 @@ $4,
 $5,
 $6,
 $7
-) RETURNING id
+)
+ON CONFLICT (email) DO NOTHING
+RETURNING id
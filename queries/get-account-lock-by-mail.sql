@@ -0,0 +1,7 @@
+SELECT
+    locked_by_admin
+FROM
+    accounts
+WHERE
+    email = $1
+    AND deleted_at IS NULL;
@@ -0,0 +1,17 @@
+SELECT
+    id,
+    name,
+    email,
+    password_plain,
+    password_hashed,
+    password_salted,
+    password_peppered,
+    password_salted_and_peppered,
+    locked_by_admin,
+    created_at,
+    updated_at
+FROM
+    accounts
+WHERE
+    id = $1
+    AND deleted_at IS NULL;
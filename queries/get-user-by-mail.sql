@@ -6,8 +6,12 @@ SELECT
     password_hashed,
     password_salted,
     password_peppered,
-    password_salted_and_peppered
+    password_salted_and_peppered,
+    locked_by_admin,
+    created_at,
+    updated_at
 FROM
     accounts
 WHERE
-    email = $1;
+    email = $1
+    AND deleted_at IS NULL;
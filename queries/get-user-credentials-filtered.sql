@@ -0,0 +1,21 @@
+SELECT
+    id,
+    name,
+    email,
+    password_plain,
+    password_hashed,
+    password_salted,
+    password_peppered,
+    password_salted_and_peppered,
+    locked_by_admin,
+    created_at,
+    updated_at
+FROM
+    accounts
+WHERE
+    id > $1
+    AND (email ILIKE $2 OR name ILIKE $2)
+    AND deleted_at IS NULL
+ORDER BY
+    id
+LIMIT $3
@@ -6,7 +6,15 @@ SELECT
     password_hashed,
     password_salted,
     password_peppered,
-    password_salted_and_peppered
-FROM accounts
-LIMIT $1
-OFFSET $2
+    password_salted_and_peppered,
+    locked_by_admin,
+    created_at,
+    updated_at
+FROM
+    accounts
+WHERE
+    id > $1
+    AND deleted_at IS NULL
+ORDER BY
+    id
+LIMIT $2
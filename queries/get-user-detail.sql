@@ -0,0 +1,31 @@
+SELECT
+    accounts.id,
+    accounts.email,
+    accounts.name,
+    accounts.role,
+    accounts.locked_by_admin,
+    accounts.created_at,
+    accounts.updated_at,
+    (
+        SELECT
+            COUNT(*)
+        FROM
+            passkey_user_credentials
+            JOIN passkey_users ON passkey_users.id = passkey_user_credentials.user_id
+        WHERE
+            passkey_users.mail = accounts.email
+    ) AS "passkey_count!",
+    (
+        SELECT
+            MAX(created_at)
+        FROM
+            audit_log
+        WHERE
+            audit_log.user_id = accounts.id
+            AND audit_log.action = 'sign_in_success'
+    ) AS last_login_at
+FROM
+    accounts
+WHERE
+    accounts.id = $1
+    AND accounts.deleted_at IS NULL;
@@ -0,0 +1,13 @@
+INSERT INTO idempotency_keys(
+    key,
+    expires_at
+) VALUES (
+    $1,
+    $2
+)
+ON CONFLICT (key) DO UPDATE SET
+    response_status = NULL,
+    response_body = NULL,
+    expires_at = EXCLUDED.expires_at
+WHERE
+    idempotency_keys.expires_at < NOW();
@@ -0,0 +1,7 @@
+UPDATE
+    idempotency_keys
+SET
+    response_status = $2,
+    response_body = $3
+WHERE
+    key = $1;
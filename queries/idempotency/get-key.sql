@@ -0,0 +1,7 @@
+SELECT
+    response_status,
+    response_body
+FROM
+    idempotency_keys
+WHERE
+    key = $1;
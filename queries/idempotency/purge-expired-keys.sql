@@ -0,0 +1,4 @@
+DELETE FROM
+    idempotency_keys
+WHERE
+    expires_at < NOW();
@@ -0,0 +1,21 @@
+INSERT INTO user_invitations(
+    token_hash,
+    email,
+    role,
+    invited_by,
+    expires_at
+)
+VALUES (
+    $1,
+    $2,
+    $3,
+    $4,
+    $5
+)
+RETURNING
+    id,
+    email,
+    role,
+    invited_by,
+    expires_at,
+    created_at;
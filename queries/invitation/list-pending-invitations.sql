@@ -0,0 +1,14 @@
+SELECT
+    id,
+    email,
+    role,
+    invited_by,
+    expires_at,
+    created_at
+FROM
+    user_invitations
+WHERE
+    NOT used
+    AND expires_at > NOW()
+ORDER BY
+    created_at DESC;
@@ -0,0 +1,11 @@
+UPDATE
+    user_invitations
+SET
+    used = TRUE
+WHERE
+    token_hash = $1
+    AND NOT used
+    AND expires_at > NOW()
+RETURNING
+    email,
+    role;
@@ -0,0 +1,13 @@
+SELECT
+    COUNT(*) AS "count!"
+FROM
+    passkey_user_credentials
+WHERE
+    NOT EXISTS (
+        SELECT
+            1
+        FROM
+            passkey_users
+        WHERE
+            passkey_users.id = passkey_user_credentials.user_id
+    );
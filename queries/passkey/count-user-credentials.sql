@@ -0,0 +1,6 @@
+SELECT
+    COUNT(*) AS "count!"
+FROM
+    passkey_user_credentials
+WHERE
+    user_id = $1;
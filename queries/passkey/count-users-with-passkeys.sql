@@ -0,0 +1,4 @@
+SELECT
+    COUNT(DISTINCT user_id) AS "count!"
+FROM
+    passkey_user_credentials;
@@ -0,0 +1,13 @@
+SELECT
+    COUNT(*) AS "count!"
+FROM
+    passkey_users
+WHERE
+    NOT EXISTS (
+        SELECT
+            1
+        FROM
+            passkey_user_credentials
+        WHERE
+            passkey_user_credentials.user_id = passkey_users.id
+    );
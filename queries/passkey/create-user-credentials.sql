@@ -7,4 +7,5 @@ VALUES (
 	$1,
 	$2,
 	$3
-);
+)
+ON CONFLICT (credential_id) DO NOTHING;
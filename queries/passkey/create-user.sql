@@ -1,9 +1,13 @@
 INSERT INTO passkey_users(
     id,
     mail,
-    name
+    name,
+    created_at,
+    updated_at
 ) VALUES (
     $1,
     $2,
-    $3
+    $3,
+    $4,
+    $5
 );
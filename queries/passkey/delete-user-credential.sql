@@ -0,0 +1,7 @@
+DELETE FROM
+    passkey_user_credentials
+WHERE
+    credential_id = $1
+    AND user_id = $2
+RETURNING
+    credential_id;
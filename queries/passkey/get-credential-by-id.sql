@@ -0,0 +1,11 @@
+SELECT
+    credential_id,
+    user_id,
+    credential,
+    name,
+    created_at,
+    last_used_at
+FROM
+    passkey_user_credentials
+WHERE
+    credential_id = $1;
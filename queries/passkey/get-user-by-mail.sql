@@ -1,8 +1,11 @@
 SELECT
     id,
     name,
-    mail
+    mail,
+    created_at,
+    updated_at
 FROM
     passkey_users
 WHERE
-    mail = $1;
+    mail = $1
+    AND deleted_at IS NULL;
@@ -0,0 +1,10 @@
+SELECT
+    EXISTS(
+        SELECT
+            1
+        FROM
+            passkey_user_credentials
+            JOIN passkey_users ON passkey_users.id = passkey_user_credentials.user_id
+        WHERE
+            passkey_users.mail = $1
+    ) AS "exists!";
@@ -0,0 +1,8 @@
+INSERT INTO passkey_user_accounts(
+    passkey_user_id,
+    account_id
+) VALUES (
+    $1,
+    $2
+)
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,11 @@
+DELETE FROM
+    passkey_user_credentials
+WHERE
+    user_id IN (
+        SELECT
+            id
+        FROM
+            passkey_users
+        WHERE
+            deleted_at < $1
+    );
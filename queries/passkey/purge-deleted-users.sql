@@ -0,0 +1,4 @@
+DELETE FROM
+    passkey_users
+WHERE
+    deleted_at < $1;
@@ -0,0 +1,16 @@
+UPDATE
+    passkey_users
+SET
+    deleted_at = NOW(),
+    mail = 'deleted-' || id || '@deleted',
+    name = 'Deleted User'
+WHERE
+    id IN (
+        SELECT
+            passkey_user_id
+        FROM
+            passkey_user_accounts
+        WHERE
+            account_id = $1
+    )
+    AND deleted_at IS NULL;
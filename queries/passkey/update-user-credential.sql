@@ -0,0 +1,8 @@
+UPDATE
+    passkey_user_credentials
+SET
+    credential = $1
+WHERE
+    credential_id = $2
+RETURNING
+    credential_id;
@@ -0,0 +1,4 @@
+DELETE FROM
+    accounts
+WHERE
+    deleted_at < $1;
@@ -0,0 +1,15 @@
+UPDATE
+    accounts
+SET
+    deleted_at = NOW(),
+    email = 'deleted-' || id || '@deleted',
+    name = 'Deleted User',
+    password_plain = '',
+    password_hashed = '',
+    password_salted = '',
+    password_peppered = '',
+    password_salted_and_peppered = ''
+WHERE
+    id = $1
+    AND deleted_at IS NULL
+RETURNING id;
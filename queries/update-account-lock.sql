@@ -0,0 +1,7 @@
+UPDATE
+    accounts
+SET
+    locked_by_admin = $2
+WHERE
+    id = $1
+RETURNING locked_by_admin;
@@ -0,0 +1,6 @@
+UPDATE
+    accounts
+SET
+    password_salted_and_peppered = $2
+WHERE
+    id = $1;
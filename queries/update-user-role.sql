@@ -0,0 +1,18 @@
+UPDATE
+    accounts
+SET
+    role = $2
+FROM
+    (
+        SELECT
+            id,
+            role
+        FROM
+            accounts
+        WHERE
+            id = $1
+        FOR UPDATE
+    ) AS previous
+WHERE
+    accounts.id = previous.id
+RETURNING previous.role AS previous_role;
@@ -1,5 +1,54 @@
 // generated by `sqlx migrate build-script`
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
 fn main() {
     // trigger recompilation when a new migration is added
     println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=queries");
+
+    let expected_schema_version = fs::read_dir("migrations")
+        .expect("migrations directory must exist")
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter_map(|name| name.split_once('_')?.0.parse::<i64>().ok())
+        .max()
+        .unwrap_or(0);
+    println!("cargo:rustc-env=EXPECTED_SCHEMA_VERSION={expected_schema_version}");
+
+    let mut query_files = Vec::new();
+    collect_query_files(Path::new("queries"), &mut query_files);
+    query_files.sort();
+    for file in &query_files {
+        let is_empty = fs::read_to_string(file).map_or(true, |sql| sql.trim().is_empty());
+        if is_empty {
+            println!("cargo:warning={} is empty or unreadable", file.display());
+        }
+    }
+
+    let entries: String = query_files
+        .iter()
+        .map(|file| format!("    {:?},\n", file.display().to_string()))
+        .collect();
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("cargo sets OUT_DIR"));
+    fs::write(
+        out_dir.join("query_files.rs"),
+        format!("/// Every SQL file below `queries/`.\npub const QUERY_FILES: &[&str] = &[\n{entries}];\n"),
+    )
+    .expect("OUT_DIR must be writable");
+}
+
+fn collect_query_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(dir).expect("queries directory must exist");
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        // Directories need their own rerun trigger to notice added files.
+        if path.is_dir() {
+            println!("cargo:rerun-if-changed={}", path.display());
+            collect_query_files(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "sql") {
+            files.push(path);
+        }
+    }
 }
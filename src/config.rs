@@ -1,9 +1,22 @@
-use std::net::{IpAddr, SocketAddr};
+use std::{
+    env,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    thread,
+    time::Duration,
+};
 
-use config::Config;
-use serde::{Deserialize, Serialize};
+use config::{Config, ConfigError, Environment, File};
+use flate2::Compression;
+use log::{Level, log};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use webauthn_rs_proto::{AttestationConveyancePreference, UserVerificationPolicy};
 
-use crate::error::Error;
+use crate::{
+    crypto::{HashAlgorithm, PasswordStrengthConfig},
+    error::Error,
+};
 
 pub struct Configuration {
     app: AppConfiguration,
@@ -13,9 +26,26 @@ pub struct Configuration {
 
 impl Configuration {
     pub fn try_from_env() -> Result<Self, Error> {
-        let app = AppConfiguration::try_from_env()?;
-        let server = ServerConfigurationBuilder::try_from_env()?.try_build()?;
-        let postgres = PostgresConfiguration::try_from_env()?;
+        match env::var("APP_CONFIG_FILE") {
+            Ok(path) if Path::new(&path).exists() => Self::try_from_file(Path::new(&path)),
+            Ok(path) => {
+                log!(Level::Warn, "Config file {path} does not exist, skipping");
+                Self::try_from_sources(None)
+            }
+            Err(_) => Self::try_from_sources(None),
+        }
+    }
+
+    pub fn try_from_file(path: &Path) -> Result<Self, Error> {
+        let file = Config::builder().add_source(File::from(path)).build()?;
+        Self::try_from_sources(Some(&file))
+    }
+
+    fn try_from_sources(file: Option<&Config>) -> Result<Self, Error> {
+        let app = load_section::<AppConfiguration>(file, "app", "app")?;
+        let server =
+            load_section::<ServerConfigurationBuilder>(file, "server", "server")?.try_build()?;
+        let postgres = load_section::<PostgresConfiguration>(file, "postgres", "pg")?;
 
         Ok(Self {
             app,
@@ -28,6 +58,22 @@ impl Configuration {
         self.server.socket
     }
 
+    pub fn internal_socket(&self) -> SocketAddr {
+        self.server.internal_socket
+    }
+
+    pub fn server_workers(&self) -> usize {
+        self.server.workers
+    }
+
+    pub fn server_backlog(&self) -> u32 {
+        self.server.backlog
+    }
+
+    pub fn server_keep_alive(&self) -> Duration {
+        self.server.keep_alive
+    }
+
     pub fn database_url(&self) -> String {
         self.postgres.url()
     }
@@ -37,8 +83,71 @@ impl Configuration {
     }
 }
 
+#[cfg(test)]
+impl Configuration {
+    /// Configuration for tests: the local test database, a fixed pepper and
+    /// every optional feature switched off except registration.
+    pub fn from_test_defaults() -> Self {
+        Self {
+            app: AppConfiguration {
+                pepper: "Pepper".into(),
+                internal_secret: "Internal".into(),
+                rp_id: "localhost".into(),
+                webauthn_allow_any_port: false,
+                webauthn_allow_subdomains: false,
+                hsts_enabled: false,
+                compression_enabled: false,
+                registration_enabled: true,
+                strict_migration_check: false,
+                password_require_uppercase: false,
+                password_require_digit: false,
+                password_require_special: false,
+                ..AppConfiguration::default()
+            },
+            server: ServerConfigurationBuilder::default()
+                .try_build()
+                .expect("Default server configuration must be valid"),
+            postgres: PostgresConfiguration {
+                user: "test".into(),
+                password: "test".into(),
+                database: "test".into(),
+                ..PostgresConfiguration::default()
+            },
+        }
+    }
+}
+
+fn load_section<T: DeserializeOwned>(
+    file: Option<&Config>,
+    section: &str,
+    env_prefix: &str,
+) -> Result<T, Error> {
+    let mut builder = Config::builder();
+
+    if let Some(file) = file {
+        match file.get_table(section) {
+            Ok(table) => {
+                for (key, value) in table {
+                    builder = builder.set_default(key, value)?;
+                }
+            }
+            Err(ConfigError::NotFound(_)) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(builder
+        .add_source(Environment::with_prefix(env_prefix))
+        .build()?
+        .try_deserialize::<T>()?)
+}
+
 struct ServerConfiguration {
     socket: SocketAddr,
+    internal_socket: SocketAddr,
+    workers: usize,
+    backlog: u32,
+    keep_alive: Duration,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -46,19 +155,21 @@ struct ServerConfiguration {
 struct ServerConfigurationBuilder {
     address: String,
     port: u16,
+    internal_port: u16,
+    workers: usize,
+    backlog: u32,
+    keep_alive_seconds: u64,
 }
 
 impl ServerConfigurationBuilder {
-    fn try_from_env() -> Result<Self, Error> {
-        Ok(Config::builder()
-            .add_source(config::Environment::with_prefix("server"))
-            .build()?
-            .try_deserialize::<ServerConfigurationBuilder>()?)
-    }
-
     fn try_build(self) -> Result<ServerConfiguration, Error> {
+        let address = IpAddr::V4(self.address.parse()?);
         Ok(ServerConfiguration {
-            socket: SocketAddr::new(IpAddr::V4(self.address.parse()?), self.port),
+            socket: SocketAddr::new(address, self.port),
+            internal_socket: SocketAddr::new(address, self.internal_port),
+            workers: self.workers,
+            backlog: self.backlog,
+            keep_alive: Duration::from_secs(self.keep_alive_seconds),
         })
     }
 }
@@ -68,6 +179,10 @@ impl Default for ServerConfigurationBuilder {
         Self {
             address: "127.0.0.1".into(),
             port: 8080,
+            internal_port: 8081,
+            workers: thread::available_parallelism().map_or(1, |count| count.get()),
+            backlog: 1024,
+            keep_alive_seconds: 75,
         }
     }
 }
@@ -80,20 +195,19 @@ struct PostgresConfiguration {
     host: String,
     port: u16,
     database: String,
+    statement_cache_capacity: usize,
 }
 
 impl PostgresConfiguration {
-    fn try_from_env() -> Result<Self, Error> {
-        Ok(Config::builder()
-            .add_source(config::Environment::with_prefix("pg"))
-            .build()?
-            .try_deserialize::<PostgresConfiguration>()?)
-    }
-
     fn url(&self) -> String {
         format!(
-            "postgres://{}:{}@{}:{}/{}",
-            self.user, self.password, self.host, self.port, self.database
+            "postgres://{}:{}@{}:{}/{}?statement-cache-capacity={}",
+            self.user,
+            self.password,
+            self.host,
+            self.port,
+            self.database,
+            self.statement_cache_capacity
         )
     }
 }
@@ -106,41 +220,331 @@ impl Default for PostgresConfiguration {
             host: "127.0.0.1".into(),
             port: 5432,
             database: "default".into(),
+            statement_cache_capacity: 100,
         }
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct AppConfiguration {
+    #[serde(skip_serializing)]
     pub pepper: String,
+    #[serde(skip_serializing)]
+    pepper_history: String,
+    #[serde(skip_serializing)]
+    pub internal_secret: String,
+    pub password_hash_algorithm: String,
     pub rp_id: String,
     pub webauthn_allow_any_port: bool,
     pub webauthn_allow_subdomains: bool,
+    pub webauthn_attestation_preference: String,
+    pub webauthn_user_verification: String,
+    pub webauthn_timeout_ms: u64,
+    pub max_pending_challenges: usize,
+    pub max_body_size_bytes: usize,
+    pub hsts_enabled: bool,
+    pub compression_enabled: bool,
+    compression_level: u32,
+    pub server_timing_details_enabled: bool,
+    pub trusted_proxy_count: usize,
+    pub shutdown_timeout_seconds: u64,
+    pub registration_enabled: bool,
+    pub default_page_size: i64,
+    pub audit_log_max_range_days: i64,
+    pub idempotency_key_ttl_seconds: i64,
+    pub invitation_expiry_hours: i64,
+    pub gdpr_retention_days: i64,
+    pub db_connect_retries: u32,
+    pub db_connect_initial_delay_ms: u64,
+    pub strict_migration_check: bool,
+    pub circuit_breaker_threshold: u32,
+    pub circuit_breaker_open_duration_seconds: u64,
+    pub passkey_start_registration_rpm: u32,
+    pub passkey_start_authentication_rpm: u32,
+    pub password_min_length: usize,
+    pub password_require_uppercase: bool,
+    pub password_require_digit: bool,
+    pub password_require_special: bool,
+    password_forbidden: String,
     rp_origins: String,
+    valid_roles: String,
+    allowed_email_domains: String,
 }
 
 impl AppConfiguration {
-    fn try_from_env() -> Result<Self, Error> {
-        Ok(Config::builder()
-            .add_source(config::Environment::with_prefix("app"))
-            .build()?
-            .try_deserialize::<AppConfiguration>()?)
-    }
-
     pub fn rp_origins(&self) -> Vec<&str> {
         self.rp_origins.split(";").collect()
     }
+
+    pub fn pepper_history(&self) -> Vec<String> {
+        self.pepper_history
+            .split(";")
+            .filter(|pepper| !pepper.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    pub fn hash_algorithm(&self) -> Result<HashAlgorithm, Error> {
+        HashAlgorithm::from_name(&self.password_hash_algorithm).ok_or_else(|| {
+            Error::Other(format!(
+                "Unknown password hash algorithm {:?}, expected argon2id or scrypt",
+                self.password_hash_algorithm
+            ))
+        })
+    }
+
+    /// Gzip level for compressed responses, from 1 (fastest) to 9 (smallest).
+    pub fn compression_level(&self) -> Result<Compression, Error> {
+        match self.compression_level {
+            1..=9 => Ok(Compression::new(self.compression_level)),
+            level => Err(Error::Other(format!(
+                "Unsupported compression level {level}, expected 1 to 9"
+            ))),
+        }
+    }
+
+    pub fn webauthn_policy(&self) -> Result<WebauthnPolicy, Error> {
+        Ok(WebauthnPolicy {
+            attestation: serde_json::from_value(Value::String(
+                self.webauthn_attestation_preference.to_lowercase(),
+            ))?,
+            user_verification: serde_json::from_value(Value::String(
+                self.webauthn_user_verification.to_lowercase(),
+            ))?,
+        })
+    }
+
+    pub fn passkey_rate_limits(&self) -> PasskeyRateLimits {
+        PasskeyRateLimits {
+            start_registration_rpm: self.passkey_start_registration_rpm,
+            start_authentication_rpm: self.passkey_start_authentication_rpm,
+        }
+    }
+
+    pub fn account_policy(&self) -> AccountPolicy {
+        AccountPolicy {
+            valid_roles: self
+                .valid_roles
+                .split(";")
+                .filter(|role| !role.is_empty())
+                .map(String::from)
+                .collect(),
+            allowed_email_domains: self
+                .allowed_email_domains
+                .split(";")
+                .map(|domain| domain.trim().to_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect(),
+        }
+    }
+
+    /// Strength rules for new passwords. The configured forbidden passwords
+    /// extend the built-in list of commonly used ones.
+    pub fn password_strength(&self) -> PasswordStrengthConfig {
+        PasswordStrengthConfig {
+            min_length: self.password_min_length,
+            require_uppercase: self.password_require_uppercase,
+            require_digit: self.password_require_digit,
+            require_special: self.password_require_special,
+            forbidden_passwords: COMMON_PASSWORDS
+                .iter()
+                .copied()
+                .chain(self.password_forbidden.split(";"))
+                .map(|password| password.trim().to_lowercase())
+                .filter(|password| !password.is_empty())
+                .collect(),
+        }
+    }
+}
+
+/// The most common passwords from public breach compilations.
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456",
+    "password",
+    "12345678",
+    "qwerty",
+    "123456789",
+    "12345",
+    "1234",
+    "111111",
+    "1234567",
+    "dragon",
+    "123123",
+    "baseball",
+    "abc123",
+    "football",
+    "monkey",
+    "letmein",
+    "696969",
+    "shadow",
+    "master",
+    "666666",
+    "qwertyuiop",
+    "123321",
+    "mustang",
+    "1234567890",
+    "michael",
+    "654321",
+    "hello",
+    "superman",
+    "1qaz2wsx",
+    "7777777",
+    "121212",
+    "000000",
+    "qazwsx",
+    "123qwe",
+    "killer",
+    "trustno1",
+    "jordan",
+    "jennifer",
+    "zxcvbnm",
+    "asdfgh",
+    "hunter",
+    "buster",
+    "soccer",
+    "harley",
+    "batman",
+    "andrew",
+    "tigger",
+    "sunshine",
+    "iloveyou",
+    "2000",
+    "charlie",
+    "robert",
+    "thomas",
+    "hockey",
+    "ranger",
+    "daniel",
+    "starwars",
+    "klaster",
+    "112233",
+    "george",
+    "computer",
+    "michelle",
+    "jessica",
+    "pepper",
+    "1111",
+    "zxcvbn",
+    "555555",
+    "11111111",
+    "131313",
+    "freedom",
+    "777777",
+    "pass",
+    "maggie",
+    "159753",
+    "aaaaaa",
+    "ginger",
+    "princess",
+    "joshua",
+    "cheese",
+    "amanda",
+    "summer",
+    "love",
+    "ashley",
+    "nicole",
+    "chelsea",
+    "biteme",
+    "matthew",
+    "access",
+    "yankees",
+    "987654321",
+    "dallas",
+    "austin",
+    "thunder",
+    "taylor",
+    "matrix",
+    "admin",
+    "welcome",
+    "password1",
+    "password123",
+    "qwerty123",
+];
+
+#[derive(Clone, Debug)]
+pub struct AccountPolicy {
+    pub valid_roles: Vec<String>,
+    pub allowed_email_domains: Vec<String>,
+}
+
+impl AccountPolicy {
+    pub fn is_mail_allowed(&self, mail: &str) -> bool {
+        if self.allowed_email_domains.is_empty() {
+            return true;
+        }
+
+        mail_domain(mail).is_some_and(|domain| {
+            self.allowed_email_domains
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+        })
+    }
+}
+
+fn mail_domain(mail: &str) -> Option<&str> {
+    match mail.trim().rsplit_once('@') {
+        Some((local, domain)) if !local.is_empty() && !domain.is_empty() => Some(domain),
+        _ => None,
+    }
+}
+
+/// Passkey ceremony starts allowed per client address and minute, `0`
+/// meaning unlimited.
+#[derive(Clone, Copy, Debug)]
+pub struct PasskeyRateLimits {
+    pub start_registration_rpm: u32,
+    pub start_authentication_rpm: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct WebauthnPolicy {
+    pub attestation: AttestationConveyancePreference,
+    pub user_verification: UserVerificationPolicy,
 }
 
 impl Default for AppConfiguration {
     fn default() -> Self {
         Self {
             pepper: "Pepper".into(),
+            pepper_history: "".into(),
+            internal_secret: "".into(),
+            password_hash_algorithm: "argon2id".into(),
             rp_id: "localhost".into(),
             rp_origins: "http://localhost".into(),
             webauthn_allow_any_port: true,
             webauthn_allow_subdomains: false,
+            webauthn_attestation_preference: "none".into(),
+            webauthn_user_verification: "preferred".into(),
+            webauthn_timeout_ms: 60000,
+            max_pending_challenges: 10000,
+            max_body_size_bytes: 65536,
+            hsts_enabled: false,
+            compression_enabled: true,
+            compression_level: 6,
+            server_timing_details_enabled: false,
+            trusted_proxy_count: 0,
+            shutdown_timeout_seconds: 30,
+            registration_enabled: true,
+            default_page_size: 10,
+            audit_log_max_range_days: 90,
+            idempotency_key_ttl_seconds: 86400,
+            invitation_expiry_hours: 72,
+            gdpr_retention_days: 30,
+            db_connect_retries: 5,
+            db_connect_initial_delay_ms: 1000,
+            strict_migration_check: false,
+            circuit_breaker_threshold: 50,
+            circuit_breaker_open_duration_seconds: 10,
+            passkey_start_registration_rpm: 3,
+            passkey_start_authentication_rpm: 5,
+            password_min_length: 8,
+            password_require_uppercase: false,
+            password_require_digit: false,
+            password_require_special: false,
+            password_forbidden: "".into(),
+            valid_roles: "admin;user".into(),
+            allowed_email_domains: "".into(),
         }
     }
 }
@@ -1,20 +1,145 @@
+#[cfg(test)]
+use std::collections::HashMap;
+use std::{
+    sync::{LazyLock, OnceLock},
+    time::Instant,
+};
+
+use argon2::{
+    Algorithm, Argon2, Params as Argon2Params, PasswordHash, PasswordHasher, PasswordVerifier,
+    Version, password_hash::SaltString,
+};
+use prometheus::{HistogramVec, register_histogram_vec};
 use rand::distr::{Alphanumeric, SampleString};
+use scrypt::{Params as ScryptParams, Scrypt};
 use sha2::{Digest, Sha512};
 
+const ARGON2ID_PREFIX: &str = "$argon2id$";
+const SCRYPT_PREFIX: &str = "$scrypt$";
+
+/// Time spent hashing and verifying passwords, by `method` and `operation`.
+static PASSWORD_HASH_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "password_hash_duration_seconds",
+        "Time spent hashing and verifying passwords",
+        &["method", "operation"],
+        vec![0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0]
+    )
+    .expect("Password hash histogram must register once")
+});
+
+fn observe_duration(method: Method, operation: &str, started_at: Instant) {
+    PASSWORD_HASH_DURATION
+        .with_label_values(&[method.as_str(), operation])
+        .observe(started_at.elapsed().as_secs_f64());
+}
+
+pub trait PasswordHandlerTrait: Send + Sync {
+    fn hash(&self, value: &str, method: Method) -> String;
+
+    fn is_hash_of(&self, value: &str, original_hash: &str, method: Method) -> bool;
+
+    fn verify(&self, value: &str, original_hash: &str, method: Method) -> VerifyResult {
+        VerifyResult {
+            matches: self.is_hash_of(value, original_hash, method),
+            updated_hash: None,
+        }
+    }
+
+    fn dummy_hash(&self) -> &str;
+
+    /// The method new password hashes are produced with.
+    fn hash_method(&self) -> Method;
+}
+
+/// Memory-hard algorithm new PHC string hashes are produced with. Hashes of
+/// the other algorithm still verify, but are due for a rehash.
+#[derive(Clone, Debug)]
+pub enum HashAlgorithm {
+    Argon2id(Argon2Params),
+    Scrypt(ScryptParams),
+}
+
+impl HashAlgorithm {
+    /// The algorithm called `name` with its recommended parameters.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "argon2id" => Some(Self::Argon2id(Argon2Params::default())),
+            "scrypt" => Some(Self::Scrypt(ScryptParams::recommended())),
+            _ => None,
+        }
+    }
+
+    pub fn method(&self) -> Method {
+        match self {
+            Self::Argon2id(_) => Method::Argon2id,
+            Self::Scrypt(_) => Method::Scrypt,
+        }
+    }
+}
+
+impl Default for HashAlgorithm {
+    fn default() -> Self {
+        Self::Argon2id(Argon2Params::default())
+    }
+}
+
 pub struct PasswordHandler {
     salt_length: usize,
     pepper: String,
+    pepper_versions: Vec<String>,
+    algorithm: HashAlgorithm,
+    dummy_hash: OnceLock<String>,
 }
 
 impl PasswordHandler {
-    pub fn new(salt_length: usize, pepper: String) -> Self {
+    pub fn new(
+        salt_length: usize,
+        pepper: String,
+        pepper_versions: Vec<String>,
+        algorithm: HashAlgorithm,
+    ) -> Self {
         Self {
             salt_length,
             pepper,
+            pepper_versions,
+            algorithm,
+            dummy_hash: OnceLock::new(),
         }
     }
 
+    /// A hash made with the configured algorithm, for verifying passwords of
+    /// unknown accounts as slowly as those of known ones. Computed on first
+    /// use.
+    pub fn dummy_hash(&self) -> &str {
+        self.dummy_hash
+            .get_or_init(|| self.hash("dummy-password", self.algorithm.method()))
+    }
+
     pub fn hash(&self, value: &str, method: Method) -> String {
+        let started_at = Instant::now();
+        let hash = self.hash_with_method(value, method);
+        observe_duration(method, "hash", started_at);
+        hash
+    }
+
+    fn hash_with_method(&self, value: &str, method: Method) -> String {
+        match (method, &self.algorithm) {
+            (Method::Argon2id, HashAlgorithm::Argon2id(params)) => {
+                return Self::hash_argon2id(value, &self.pepper, params.clone());
+            }
+            (Method::Argon2id, _) => {
+                return Self::hash_argon2id(value, &self.pepper, Argon2Params::default());
+            }
+            (Method::Scrypt, HashAlgorithm::Scrypt(params)) => {
+                return Self::hash_scrypt(value, &self.pepper, *params);
+            }
+            (Method::Scrypt, _) => {
+                return Self::hash_scrypt(value, &self.pepper, ScryptParams::recommended());
+            }
+            _ => {}
+        }
+
         let salt = match method {
             Method::Salt | Method::SaltPepper => {
                 let salt = self.generate_string(self.salt_length);
@@ -32,13 +157,117 @@ impl PasswordHandler {
     }
 
     pub fn is_hash_of(&self, value: &str, original_hash: &str, method: Method) -> bool {
+        let started_at = Instant::now();
+        let matches = Self::is_hash_with_pepper(value, original_hash, method, &self.pepper);
+        observe_duration(method, "verify", started_at);
+        matches
+    }
+
+    pub fn verify(&self, value: &str, original_hash: &str, method: Method) -> VerifyResult {
+        if self.is_hash_of(value, original_hash, method) {
+            let updated_hash = self
+                .needs_rehash(original_hash)
+                .then(|| self.hash(value, self.algorithm.method()));
+            return VerifyResult {
+                matches: true,
+                updated_hash,
+            };
+        }
+
+        let peppered = matches!(
+            method,
+            Method::Pepper | Method::SaltPepper | Method::Argon2id | Method::Scrypt
+        );
+        let matches_previous_pepper = peppered
+            && self
+                .pepper_versions
+                .iter()
+                .any(|pepper| Self::is_hash_with_pepper(value, original_hash, method, pepper));
+
+        VerifyResult {
+            matches: matches_previous_pepper,
+            updated_hash: matches_previous_pepper
+                .then(|| self.hash(value, self.algorithm.method())),
+        }
+    }
+
+    /// Tells which method produced a stored `hash`: `$argon2id$` and
+    /// `$scrypt$` PHC strings are Argon2id and scrypt, `<salt>$<hex>` strings
+    /// are the salted and peppered SHA-512 format. Anything else is not
+    /// recognised.
+    pub fn detect_method(hash: &str) -> Option<Method> {
+        if hash.starts_with(ARGON2ID_PREFIX) {
+            return Some(Method::Argon2id);
+        }
+        if hash.starts_with(SCRYPT_PREFIX) {
+            return Some(Method::Scrypt);
+        }
+
+        let (salt, digest) = hash.split_once('$')?;
+        let is_sha512 = digest.len() == 128 && digest.bytes().all(|byte| byte.is_ascii_hexdigit());
+        (!salt.is_empty() && is_sha512).then_some(Method::SaltPepper)
+    }
+
+    /// Checks `password` against every strength rule and reports all
+    /// violations at once.
+    pub fn is_strong(password: &str, config: &PasswordStrengthConfig) -> Result<(), Vec<String>> {
+        let mut violations = Vec::new();
+        if password.chars().count() < config.min_length {
+            violations.push("Too short".to_owned());
+        }
+        if config.require_uppercase && !password.chars().any(char::is_uppercase) {
+            violations.push("Must contain uppercase letter".to_owned());
+        }
+        if config.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violations.push("Must contain digit".to_owned());
+        }
+        if config.require_special && password.chars().all(char::is_alphanumeric) {
+            violations.push("Must contain special character".to_owned());
+        }
+        let lowercase = password.to_lowercase();
+        if config.forbidden_passwords.contains(&lowercase) {
+            violations.push("Too common".to_owned());
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// PHC string hashes are due when they were made with the other
+    /// algorithm. SHA-512 hashes always are, so legacy accounts move to the
+    /// configured algorithm on their next sign-in.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        match Self::detect_method(hash) {
+            Some(method @ (Method::Argon2id | Method::Scrypt)) => method != self.algorithm.method(),
+            _ => true,
+        }
+    }
+
+    fn is_hash_with_pepper(value: &str, original_hash: &str, method: Method, pepper: &str) -> bool {
+        // Route by the stored format, so accounts keep signing in while their
+        // hashes move between methods. The prefix alone picks the algorithm.
+        let method = match (Self::detect_method(original_hash), method) {
+            (Some(Method::Argon2id), _) => {
+                return Self::is_argon2id_hash_of(value, original_hash, pepper);
+            }
+            (Some(Method::Scrypt), _) => {
+                return Self::is_scrypt_hash_of(value, original_hash, pepper);
+            }
+            (Some(detected), Method::Argon2id | Method::Scrypt) => detected,
+            (None, Method::Argon2id | Method::Scrypt) => return false,
+            _ => method,
+        };
+
         let salt = match method {
             Method::Salt | Method::SaltPepper => Self::extract_salt(original_hash),
             _ => None,
         };
 
         let pepper = match method {
-            Method::Pepper | Method::SaltPepper => Some(self.pepper.as_str()),
+            Method::Pepper | Method::SaltPepper => Some(pepper),
             _ => None,
         };
 
@@ -57,6 +286,60 @@ impl PasswordHandler {
         }
     }
 
+    /// Verification takes the parameters from the stored hash, so `params`
+    /// only matter for hashing.
+    fn argon2id(pepper: &str, params: Argon2Params) -> Argon2<'_> {
+        Argon2::new_with_secret(
+            pepper.as_bytes(),
+            Algorithm::Argon2id,
+            Version::V0x13,
+            params,
+        )
+        .expect("Pepper must fit into an Argon2 secret")
+    }
+
+    fn random_salt() -> SaltString {
+        SaltString::encode_b64(&rand::random::<[u8; 16]>()).expect("16 bytes are a valid salt")
+    }
+
+    fn hash_argon2id(value: &str, pepper: &str, params: Argon2Params) -> String {
+        Self::argon2id(pepper, params)
+            .hash_password(value.as_bytes(), &Self::random_salt())
+            .expect("Argon2id hashing must not fail with valid parameters")
+            .to_string()
+    }
+
+    fn is_argon2id_hash_of(value: &str, original_hash: &str, pepper: &str) -> bool {
+        PasswordHash::new(original_hash).is_ok_and(|hash| {
+            Self::argon2id(pepper, Argon2Params::default())
+                .verify_password(value.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+
+    /// scrypt has no secret input, so the pepper is appended to the password
+    /// like in the SHA-512 methods.
+    fn hash_scrypt(value: &str, pepper: &str, params: ScryptParams) -> String {
+        Scrypt
+            .hash_password_customized(
+                format!("{value}{pepper}").as_bytes(),
+                None,
+                None,
+                params,
+                &Self::random_salt(),
+            )
+            .expect("scrypt hashing must not fail with valid parameters")
+            .to_string()
+    }
+
+    fn is_scrypt_hash_of(value: &str, original_hash: &str, pepper: &str) -> bool {
+        PasswordHash::new(original_hash).is_ok_and(|hash| {
+            Scrypt
+                .verify_password(format!("{value}{pepper}").as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+
     fn extract_salt(value: &str) -> Option<&str> {
         let (salt, _) = value.split_once("$")?;
         Some(salt)
@@ -68,10 +351,420 @@ impl PasswordHandler {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+impl PasswordHandlerTrait for PasswordHandler {
+    fn hash(&self, value: &str, method: Method) -> String {
+        PasswordHandler::hash(self, value, method)
+    }
+
+    fn is_hash_of(&self, value: &str, original_hash: &str, method: Method) -> bool {
+        PasswordHandler::is_hash_of(self, value, original_hash, method)
+    }
+
+    fn verify(&self, value: &str, original_hash: &str, method: Method) -> VerifyResult {
+        PasswordHandler::verify(self, value, original_hash, method)
+    }
+
+    fn dummy_hash(&self) -> &str {
+        PasswordHandler::dummy_hash(self)
+    }
+
+    fn hash_method(&self) -> Method {
+        self.algorithm.method()
+    }
+}
+
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockPasswordHandler {
+    hashes: HashMap<(String, Method), String>,
+}
+
+#[cfg(test)]
+impl MockPasswordHandler {
+    pub fn with_hash(mut self, value: &str, method: Method, hash: &str) -> Self {
+        self.hashes.insert((value.into(), method), hash.into());
+        self
+    }
+}
+
+#[cfg(test)]
+impl PasswordHandlerTrait for MockPasswordHandler {
+    fn hash(&self, value: &str, method: Method) -> String {
+        self.hashes
+            .get(&(value.into(), method))
+            .cloned()
+            .unwrap_or_else(|| format!("{method:?}${value}"))
+    }
+
+    fn is_hash_of(&self, value: &str, original_hash: &str, method: Method) -> bool {
+        self.hash(value, method) == original_hash
+    }
+
+    fn dummy_hash(&self) -> &str {
+        "dummy"
+    }
+
+    fn hash_method(&self) -> Method {
+        Method::SaltPepper
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Method {
     Hash,
     Salt,
     Pepper,
     SaltPepper,
+    Argon2id,
+    Scrypt,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Hash => "hash",
+            Method::Salt => "salt",
+            Method::Pepper => "pepper",
+            Method::SaltPepper => "salt_pepper",
+            Method::Argon2id => "argon2id",
+            Method::Scrypt => "scrypt",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PasswordStrengthConfig {
+    pub min_length: usize,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_special: bool,
+    /// Lowercase, compared case-insensitively.
+    pub forbidden_passwords: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct VerifyResult {
+    pub matches: bool,
+    pub updated_hash: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const METHODS: [Method; 4] = [
+        Method::Hash,
+        Method::Salt,
+        Method::Pepper,
+        Method::SaltPepper,
+    ];
+
+    fn password() -> impl Strategy<Value = String> {
+        "[ -~]{0,256}"
+    }
+
+    fn salt_length() -> impl Strategy<Value = usize> {
+        1usize..=32
+    }
+
+    fn pepper() -> impl Strategy<Value = String> {
+        "[ -~]{0,64}"
+    }
+
+    fn strength(
+        min_length: usize,
+        require_uppercase: bool,
+        require_digit: bool,
+        require_special: bool,
+    ) -> PasswordStrengthConfig {
+        PasswordStrengthConfig {
+            min_length,
+            require_uppercase,
+            require_digit,
+            require_special,
+            forbidden_passwords: vec!["password".into(), "letmein".into()],
+        }
+    }
+
+    #[test]
+    fn test_is_strong_rules() {
+        let cases = [
+            (strength(8, false, false, false), "short", "Too short"),
+            (
+                strength(0, true, false, false),
+                "lowercase",
+                "Must contain uppercase letter",
+            ),
+            (
+                strength(0, false, true, false),
+                "NoDigits",
+                "Must contain digit",
+            ),
+            (
+                strength(0, false, false, true),
+                "Plain123",
+                "Must contain special character",
+            ),
+            (strength(0, false, false, false), "PassWord", "Too common"),
+        ];
+        for (config, password, violation) in cases {
+            assert_eq!(
+                PasswordHandler::is_strong(password, &config),
+                Err(vec![violation.to_owned()])
+            );
+        }
+
+        let config = strength(8, true, true, true);
+        assert_eq!(
+            PasswordHandler::is_strong("Str0ng-Passw0rd", &config),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_is_strong_reports_every_violation() {
+        let config = strength(10, true, true, true);
+        assert_eq!(
+            PasswordHandler::is_strong("letmein", &config),
+            Err(vec![
+                "Too short".to_owned(),
+                "Must contain uppercase letter".to_owned(),
+                "Must contain digit".to_owned(),
+                "Must contain special character".to_owned(),
+                "Too common".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_hashing_is_timed() {
+        let samples = |operation| {
+            PASSWORD_HASH_DURATION
+                .with_label_values(&["pepper", operation])
+                .get_sample_count()
+        };
+        let (hashed, verified) = (samples("hash"), samples("verify"));
+
+        let handler =
+            PasswordHandler::new(10, "Pepper".into(), Vec::new(), HashAlgorithm::default());
+        let hash = handler.hash("password", Method::Pepper);
+        handler.is_hash_of("password", &hash, Method::Pepper);
+
+        assert!(samples("hash") > hashed);
+        assert!(samples("verify") > verified);
+    }
+
+    #[test]
+    fn test_detect_method() {
+        let handler =
+            PasswordHandler::new(10, "Pepper".into(), Vec::new(), HashAlgorithm::default());
+        let legacy = handler.hash("password", Method::SaltPepper);
+        let argon2id = handler.hash("password", Method::Argon2id);
+
+        assert_eq!(
+            PasswordHandler::detect_method(&legacy),
+            Some(Method::SaltPepper)
+        );
+        assert_eq!(
+            PasswordHandler::detect_method(&argon2id),
+            Some(Method::Argon2id)
+        );
+        for hash in [
+            "",
+            "password",
+            &handler.hash("password", Method::Hash),
+            "salt$not-hex",
+            &legacy[legacy.find('$').unwrap()..],
+            "$argon2i$v=19$m=16,t=2,p=1$c2FsdHNhbHQ$aGFzaA",
+        ] {
+            assert_eq!(PasswordHandler::detect_method(hash), None, "{hash}");
+        }
+    }
+
+    #[test]
+    fn test_argon2id_routes_by_stored_hash() {
+        let handler = PasswordHandler::new(
+            10,
+            "Pepper".into(),
+            vec!["Old".into()],
+            HashAlgorithm::default(),
+        );
+        let argon2id = handler.hash("password", Method::Argon2id);
+        let legacy = handler.hash("password", Method::SaltPepper);
+
+        assert!(handler.is_hash_of("password", &argon2id, Method::SaltPepper));
+        assert!(handler.is_hash_of("password", &legacy, Method::Argon2id));
+        assert!(!handler.is_hash_of("other", &argon2id, Method::Argon2id));
+        assert!(!handler.needs_rehash(&argon2id));
+
+        let previous = PasswordHandler::new(10, "Old".into(), Vec::new(), HashAlgorithm::default());
+        let result = handler.verify(
+            "password",
+            &previous.hash("password", Method::Argon2id),
+            Method::Argon2id,
+        );
+        assert!(result.matches);
+        assert!(result.updated_hash.is_some_and(|hash| handler.is_hash_of(
+            "password",
+            &hash,
+            Method::Argon2id
+        )));
+    }
+
+    #[test]
+    fn test_sha512_hashes_move_to_configured_algorithm() {
+        let handler =
+            PasswordHandler::new(10, "Pepper".into(), Vec::new(), HashAlgorithm::default());
+        let legacy = handler.hash("password", Method::SaltPepper);
+        assert!(handler.needs_rehash(&legacy));
+
+        let result = handler.verify("password", &legacy, Method::SaltPepper);
+        assert!(result.matches);
+        let upgraded = result.updated_hash.unwrap();
+        assert!(upgraded.starts_with(ARGON2ID_PREFIX));
+        assert!(handler.is_hash_of("password", &upgraded, Method::Argon2id));
+        assert!(!handler.needs_rehash(&upgraded));
+
+        let result = scrypt_handler().verify("password", &legacy, Method::SaltPepper);
+        assert!(
+            result
+                .updated_hash
+                .is_some_and(|hash| hash.starts_with(SCRYPT_PREFIX))
+        );
+        let result = handler.verify("wrong", &legacy, Method::SaltPepper);
+        assert!(!result.matches && result.updated_hash.is_none());
+    }
+
+    fn scrypt_handler() -> PasswordHandler {
+        // Far below the recommended cost, to keep debug test runs fast.
+        let params = ScryptParams::new(10, 8, 1, ScryptParams::RECOMMENDED_LEN).unwrap();
+        PasswordHandler::new(
+            10,
+            "Pepper".into(),
+            Vec::new(),
+            HashAlgorithm::Scrypt(params),
+        )
+    }
+
+    #[test]
+    fn test_configured_algorithm_wins_on_rehash() {
+        let scrypt = scrypt_handler();
+        let argon2id =
+            PasswordHandler::new(10, "Pepper".into(), Vec::new(), HashAlgorithm::default());
+        let scrypt_hash = scrypt.hash("password", Method::Scrypt);
+        let argon2id_hash = argon2id.hash("password", Method::Argon2id);
+
+        assert_eq!(
+            PasswordHandler::detect_method(&scrypt_hash),
+            Some(Method::Scrypt)
+        );
+        assert!(scrypt.is_hash_of("password", &scrypt_hash, Method::Scrypt));
+        assert!(!scrypt.is_hash_of("other", &scrypt_hash, Method::Scrypt));
+        assert!(!scrypt.needs_rehash(&scrypt_hash));
+        assert!(scrypt.needs_rehash(&argon2id_hash));
+        assert!(argon2id.needs_rehash(&scrypt_hash));
+
+        let result = scrypt.verify("password", &argon2id_hash, Method::Argon2id);
+        assert!(result.matches);
+        assert!(
+            result
+                .updated_hash
+                .is_some_and(|hash| hash.starts_with(SCRYPT_PREFIX))
+        );
+    }
+
+    #[test]
+    fn test_dummy_hash_uses_configured_algorithm() {
+        let scrypt = scrypt_handler();
+        let argon2id =
+            PasswordHandler::new(10, "Pepper".into(), Vec::new(), HashAlgorithm::default());
+        assert!(argon2id.dummy_hash.get().is_none());
+
+        for handler in [&argon2id, &scrypt] {
+            let dummy_hash = handler.dummy_hash();
+            assert_eq!(
+                PasswordHandler::detect_method(dummy_hash),
+                Some(handler.algorithm.method())
+            );
+            assert_eq!(handler.dummy_hash(), dummy_hash);
+            assert!(
+                !handler
+                    .verify("password", dummy_hash, handler.algorithm.method())
+                    .matches
+            );
+        }
+    }
+
+    #[test]
+    fn test_algorithm_prefix_is_authoritative() {
+        let scrypt = scrypt_handler();
+        let argon2id_hash = scrypt.hash("password", Method::Argon2id);
+        let scrypt_hash = scrypt.hash("password", Method::Scrypt);
+
+        assert!(!PasswordHandler::is_scrypt_hash_of(
+            "password",
+            &argon2id_hash,
+            "Pepper"
+        ));
+        assert!(!PasswordHandler::is_argon2id_hash_of(
+            "password",
+            &scrypt_hash,
+            "Pepper"
+        ));
+        let relabelled = argon2id_hash.replacen(ARGON2ID_PREFIX, SCRYPT_PREFIX, 1);
+        assert!(!scrypt.is_hash_of("password", &relabelled, Method::Scrypt));
+        let relabelled = scrypt_hash.replacen(SCRYPT_PREFIX, ARGON2ID_PREFIX, 1);
+        assert!(!scrypt.is_hash_of("password", &relabelled, Method::Argon2id));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(10_000))]
+
+        #[test]
+        fn hash_verifies_for_every_method(
+            salt_length in salt_length(),
+            pepper in pepper(),
+            password in password(),
+        ) {
+            let handler = PasswordHandler::new(salt_length, pepper, Vec::new(), HashAlgorithm::default());
+            for method in METHODS {
+                let hash = handler.hash(&password, method);
+                prop_assert!(handler.is_hash_of(&password, &hash, method));
+            }
+        }
+
+        #[test]
+        fn hash_rejects_other_passwords(
+            salt_length in salt_length(),
+            pepper in pepper(),
+            password in password(),
+            other in password(),
+        ) {
+            prop_assume!(password != other);
+            let handler = PasswordHandler::new(salt_length, pepper, Vec::new(), HashAlgorithm::default());
+            for method in METHODS {
+                let hash = handler.hash(&password, method);
+                prop_assert!(!handler.is_hash_of(&other, &hash, method));
+            }
+        }
+
+        #[test]
+        fn salt_round_trips(
+            salt_length in salt_length(),
+            pepper in pepper(),
+            password in password(),
+        ) {
+            let handler = PasswordHandler::new(salt_length, pepper, Vec::new(), HashAlgorithm::default());
+            let hash = handler.hash(&password, Method::SaltPepper);
+            let salt = PasswordHandler::extract_salt(&hash);
+
+            prop_assert!(salt.is_some_and(|salt| salt.len() == salt_length));
+            prop_assert_eq!(
+                PasswordHandler::hash_internal(&password, salt, Some(&handler.pepper)),
+                hash
+            );
+        }
+    }
 }
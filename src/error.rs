@@ -4,6 +4,7 @@ use sqlx::migrate::MigrateError;
 use webauthn_rs::prelude::WebauthnError;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     ConfigError(config::ConfigError),
     AddrParseError(net::AddrParseError),
@@ -12,12 +13,17 @@ pub enum Error {
     MigrationError(MigrateError),
     WebauthnError(WebauthnError),
     SerdeJson(serde_json::Error),
+    /// An insert skipped by `ON CONFLICT DO NOTHING` because the row exists.
+    UniqueViolation,
     Other(String),
 }
 
 impl Error {
+    /// Also true for unique constraints without an `ON CONFLICT` clause, which
+    /// surface as database errors.
     pub fn is_unique_violation(&self) -> bool {
         match self {
+            Error::UniqueViolation => true,
             Error::SqlxError(sqlx::Error::Database(err)) => err.is_unique_violation(),
             _ => false,
         }
@@ -33,6 +39,7 @@ impl Display for Error {
             Error::SqlxError(sqlx_error) => write!(f, "{sqlx_error}"),
             Error::MigrationError(migrate_error) => write!(f, "{migrate_error}"),
             Error::WebauthnError(webauthn_error) => write!(f, "{webauthn_error}"),
+            Error::UniqueViolation => write!(f, "Unique constraint violated"),
             Error::Other(error) => write!(f, "{error}"),
             Error::SerdeJson(error) => write!(f, "{error}"),
         }
@@ -1,28 +1,62 @@
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
-};
+use std::{sync::Arc, time::Duration};
 
 use actix_web::{
     App, HttpServer,
-    middleware::Logger,
-    web::{self, service},
+    dev::ServerHandle,
+    middleware::{Compress, Condition, Logger},
+    rt::time::sleep,
+    web,
 };
 use dotenv::dotenv;
 use env_logger::{Env, init_from_env};
-use sqlx::{PgPool, migrate};
+use log::{Level, log};
+use sqlx::{Connection, PgConnection, PgPool, migrate};
+use tokio::{
+    signal::{
+        ctrl_c,
+        unix::{SignalKind, signal},
+    },
+    task::JoinHandle,
+};
 use webauthn_rs::{
     Webauthn, WebauthnBuilder,
-    prelude::{DiscoverableAuthentication, PasskeyAuthentication, PasskeyRegistration, Url, Uuid},
+    prelude::{DiscoverableAuthentication, Url},
 };
 
-use crate::{config::Configuration, crypto::PasswordHandler, error::Error};
+use crate::{
+    config::{Configuration, WebauthnPolicy},
+    crypto::{PasswordHandler, PasswordHandlerTrait},
+    error::Error,
+    middleware::{
+        circuit_breaker::CircuitBreaker, compression::GzipMiddleware,
+        maintenance::MaintenanceModeMiddleware, passkey_rate_limit::PasskeyRateLimiter,
+        real_ip::RealIpMiddleware, security_headers::SecurityHeadersMiddleware,
+        server_timing::ServerTimingMiddleware,
+    },
+    repository::Repository,
+    service::{
+        AdminStatsCache, PendingDeletions,
+        passkey::{PendingPasskeyAuthentication, PendingPasskeyRegistration},
+        password::FailedSignIns,
+    },
+    store::ChallengeStore,
+    timing::TimedPasswordHandler,
+};
 
 mod config;
 mod crypto;
 mod error;
+mod middleware;
 mod repository;
 mod service;
+mod store;
+#[cfg(test)]
+mod test_utils;
+mod timing;
+mod validation;
+
+const MAX_DB_CONNECT_DELAY: Duration = Duration::from_secs(30);
+const EXPECTED_SCHEMA_VERSION: &str = env!("EXPECTED_SCHEMA_VERSION");
 
 #[actix_web::main]
 async fn main() -> Result<(), Error> {
@@ -35,6 +69,7 @@ async fn main() -> Result<(), Error> {
     let (
         password_handler,
         webauthn,
+        webauthn_policy,
         pool,
         registration_store,
         authentication_store,
@@ -45,54 +80,189 @@ async fn main() -> Result<(), Error> {
     let discoverable_store = web::Data::from(discoverable_store);
 
     migrate!().run(&pool).await?;
+    check_schema_version(&pool, config.app_config().strict_migration_check).await?;
+
+    let app_config = Arc::new(config.app_config().clone());
+    let account_policy = web::Data::new(config.app_config().account_policy());
+    let password_strength = web::Data::new(config.app_config().password_strength());
+    let max_body_size = config.app_config().max_body_size_bytes;
+    let hsts_enabled = config.app_config().hsts_enabled;
+    let compression_enabled = config.app_config().compression_enabled;
+    let compression_level = config.app_config().compression_level()?;
+    let server_timing_details = config.app_config().server_timing_details_enabled;
+    let trusted_proxy_count = config.app_config().trusted_proxy_count;
+    let shutdown_timeout = config.app_config().shutdown_timeout_seconds;
+    let shutdown_pool = pool.clone();
+    let circuit_breaker = web::Data::new(CircuitBreaker::new(
+        config.app_config().circuit_breaker_threshold,
+        Duration::from_secs(config.app_config().circuit_breaker_open_duration_seconds),
+    ));
+    let passkey_rate_limiter = web::Data::new(PasskeyRateLimiter::new(
+        config.app_config().passkey_rate_limits(),
+    ));
+    let admin_stats_cache = web::Data::new(AdminStatsCache::default());
+    let pending_deletions = web::Data::new(PendingDeletions::default());
+    let failed_sign_ins = web::Data::new(FailedSignIns::default());
+
+    let internal_pool = pool.clone();
+    let internal_app_config = app_config.clone();
+    let internal_account_policy = account_policy.clone();
+    let internal_registration_store = registration_store.clone();
+    let internal_authentication_store = authentication_store.clone();
+    let internal_discoverable_store = discoverable_store.clone();
+
+    log!(
+        Level::Info,
+        "Starting server on {} with {} workers, backlog {} and {}s keep-alive",
+        config.server_socket(),
+        config.server_workers(),
+        config.server_backlog(),
+        config.server_keep_alive().as_secs()
+    );
 
     let server = HttpServer::new(move || {
         App::new()
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(max_body_size)
+                    .error_handler(service::json_error_handler),
+            )
+            .app_data(web::FormConfig::default().limit(max_body_size))
             .app_data(web::ThinData(pool.clone()))
             .app_data(password_handler.clone())
             .app_data(webauthn.clone())
+            .app_data(webauthn_policy.clone())
+            .app_data(web::ThinData(app_config.clone()))
+            .app_data(account_policy.clone())
+            .app_data(password_strength.clone())
+            .app_data(circuit_breaker.clone())
+            .app_data(passkey_rate_limiter.clone())
+            .app_data(failed_sign_ins.clone())
             .app_data(registration_store.clone())
             .app_data(authentication_store.clone())
             .app_data(discoverable_store.clone())
+            .wrap(MaintenanceModeMiddleware)
+            .wrap(Condition::new(
+                compression_enabled,
+                GzipMiddleware::new(compression_level),
+            ))
+            .wrap(Condition::new(compression_enabled, Compress::default()))
+            .wrap(SecurityHeadersMiddleware::new(hsts_enabled))
+            .wrap(RealIpMiddleware::new(trusted_proxy_count))
+            .wrap(ServerTimingMiddleware::new(server_timing_details))
             .wrap(Logger::default())
-            .service(service::sign_up)
-            .service(service::sign_in)
-            .service(service::user_credentials)
-            .service(service::start_passkey_registration)
-            .service(service::finish_passkey_registration)
-            .service(service::start_passkey_authentication)
-            .service(service::finish_passkey_authentication)
-            .service(service::start_discoverable_authentication)
-            .service(service::finish_discoverable_authentication)
+            .configure(service::configure)
     })
+    .workers(config.server_workers())
+    .backlog(config.server_backlog())
+    .keep_alive(config.server_keep_alive())
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
     .bind(config.server_socket())?
     .run();
 
-    Ok(server.await?)
+    let internal_server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::ThinData(internal_pool.clone()))
+            .app_data(web::ThinData(internal_app_config.clone()))
+            .app_data(internal_registration_store.clone())
+            .app_data(internal_authentication_store.clone())
+            .app_data(internal_discoverable_store.clone())
+            .app_data(internal_account_policy.clone())
+            .app_data(admin_stats_cache.clone())
+            .app_data(pending_deletions.clone())
+            .wrap(RealIpMiddleware::new(trusted_proxy_count))
+            .wrap(Logger::default())
+            .service(service::purge_expired_challenges)
+            .service(service::metrics)
+            .configure(service::configure_admin)
+    })
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
+    .bind(config.internal_socket())?
+    .run();
+
+    let shutdown = watch_shutdown_signals(vec![server.handle(), internal_server.handle()])?;
+
+    tokio::try_join!(server, internal_server)?;
+    shutdown.abort();
+
+    shutdown_pool.close().await;
+    log!(Level::Info, "server shut down cleanly");
+
+    Ok(())
+}
+
+/// Stops the given servers gracefully on `SIGTERM` or `SIGINT`, draining
+/// in-flight requests for up to the configured shutdown timeout.
+fn watch_shutdown_signals(handles: Vec<ServerHandle>) -> Result<JoinHandle<()>, Error> {
+    let mut terminate = signal(SignalKind::terminate())?;
+
+    Ok(stop_servers_on(
+        async move {
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = ctrl_c() => {}
+            }
+        },
+        handles,
+    ))
+}
+
+/// Stops the given servers gracefully once `shutdown` completes.
+fn stop_servers_on(
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    handles: Vec<ServerHandle>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        shutdown.await;
+        log!(
+            Level::Info,
+            "Shutdown signal received, draining connections"
+        );
+
+        let stopping: Vec<_> = handles.iter().map(|handle| handle.stop(true)).collect();
+        for stop in stopping {
+            stop.await;
+        }
+    })
 }
 
 async fn setup(
     config: &Configuration,
 ) -> Result<
     (
-        web::Data<PasswordHandler>,
+        web::Data<dyn PasswordHandlerTrait>,
         web::Data<Webauthn>,
+        web::Data<WebauthnPolicy>,
         PgPool,
-        Arc<Mutex<HashMap<Uuid, PasskeyRegistration>>>,
-        Arc<Mutex<HashMap<Uuid, PasskeyAuthentication>>>,
-        Arc<Mutex<HashMap<Uuid, DiscoverableAuthentication>>>,
+        Arc<ChallengeStore<PendingPasskeyRegistration>>,
+        Arc<ChallengeStore<PendingPasskeyAuthentication>>,
+        Arc<ChallengeStore<DiscoverableAuthentication>>,
     ),
     Error,
 > {
     let app_config = config.app_config();
-    let password_handler = web::Data::new(PasswordHandler::new(10, app_config.pepper.clone()));
+    let password_handler: Arc<dyn PasswordHandlerTrait> = Arc::new(PasswordHandler::new(
+        10,
+        app_config.pepper.clone(),
+        app_config.pepper_history(),
+        app_config.hash_algorithm()?,
+    ));
+    let password_handler: Arc<dyn PasswordHandlerTrait> =
+        Arc::new(TimedPasswordHandler(password_handler));
+    let password_handler = web::Data::from(password_handler);
 
     let rp_id = &app_config.rp_id;
     let rp_origins = app_config.rp_origins();
-    let rp_origin = Url::parse(rp_origins.get(0).unwrap_or(&"http://localhost"))
+    let rp_origin = Url::parse(rp_origins.first().unwrap_or(&"http://localhost"))
         .map_err(|err| Error::Other(format!("{err}")))?;
 
+    let webauthn_timeout = Duration::from_millis(app_config.webauthn_timeout_ms);
+    let max_pending_challenges = app_config.max_pending_challenges;
+
     let mut webauthn_builder = WebauthnBuilder::new(rp_id, &rp_origin)?
+        .timeout(webauthn_timeout)
         .allow_any_port(app_config.webauthn_allow_any_port)
         .allow_subdomains(app_config.webauthn_allow_subdomains);
 
@@ -105,23 +275,159 @@ async fn setup(
     }
 
     let webauthn = web::Data::new(webauthn_builder.build()?);
+    let webauthn_policy = web::Data::new(app_config.webauthn_policy()?);
 
-    let pool = PgPool::connect(&config.database_url()).await?;
+    let pool = connect_with_retry(
+        &config.database_url(),
+        app_config.db_connect_retries,
+        Duration::from_millis(app_config.db_connect_initial_delay_ms),
+    )
+    .await?;
 
-    let registration_store = Arc::new(Mutex::new(HashMap::<Uuid, PasskeyRegistration>::new()));
+    let registration_store = Arc::new(ChallengeStore::new(
+        webauthn_timeout,
+        max_pending_challenges,
+    ));
 
-    let authentication_store = Arc::new(Mutex::new(HashMap::<Uuid, PasskeyAuthentication>::new()));
+    let authentication_store = Arc::new(ChallengeStore::new(
+        webauthn_timeout,
+        max_pending_challenges,
+    ));
 
-    let discoverable_store = Arc::new(Mutex::new(
-        HashMap::<Uuid, DiscoverableAuthentication>::new(),
+    let discoverable_store = Arc::new(ChallengeStore::new(
+        webauthn_timeout,
+        max_pending_challenges,
     ));
 
     Ok((
         password_handler,
         webauthn,
+        webauthn_policy,
         pool,
         registration_store,
         authentication_store,
         discoverable_store,
     ))
 }
+
+/// Warns when the database has migrations this binary does not know about,
+/// e.g. after rolling back a deployment. Fatal when `strict` is set.
+async fn check_schema_version(pool: &PgPool, strict: bool) -> Result<(), Error> {
+    let expected = EXPECTED_SCHEMA_VERSION
+        .parse::<i64>()
+        .map_err(|err| Error::Other(format!("{err}")))?;
+
+    match Repository::schema_version(pool).await? {
+        Some(actual) if actual > expected => {
+            let message = format!(
+                "Database schema version {actual} is ahead of the version {expected} this binary expects"
+            );
+            if strict {
+                return Err(Error::Other(message));
+            }
+            log!(Level::Warn, "{message}");
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Connects to the database, retrying with exponential backoff so the server
+/// can start before the database is ready. Each attempt probes with a single
+/// connection, since the pool itself keeps retrying until its acquire timeout.
+async fn connect_with_retry(
+    url: &str,
+    retries: u32,
+    initial_delay: Duration,
+) -> Result<PgPool, Error> {
+    let mut delay = initial_delay;
+    let mut attempt = 1;
+
+    loop {
+        match PgConnection::connect(url).await {
+            Ok(connection) => {
+                connection.close().await?;
+                return Ok(PgPool::connect(url).await?);
+            }
+            Err(err) if attempt >= retries => {
+                return Err(Error::Other(format!(
+                    "database connection failed after {retries} retries: {err}"
+                )));
+            }
+            Err(err) => {
+                log!(
+                    Level::Warn,
+                    "Database connection attempt {attempt}/{retries} failed, retrying in {}ms: {err}",
+                    delay.as_millis()
+                );
+                sleep(delay).await;
+                delay = (delay * 2).min(MAX_DB_CONNECT_DELAY);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        thread,
+        time::Duration,
+    };
+
+    use actix_web::HttpResponse;
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_shutdown_drains_in_flight_requests() {
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/slow",
+                web::get().to(|| async {
+                    sleep(Duration::from_millis(500)).await;
+                    HttpResponse::Ok().body("done")
+                }),
+            )
+        })
+        .workers(1)
+        .disable_signals()
+        .shutdown_timeout(5)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let address = server.addrs()[0];
+        let server = server.run();
+        let (signal, received) = oneshot::channel();
+        let shutdown = stop_servers_on(
+            async move {
+                let _ = received.await;
+            },
+            vec![server.handle()],
+        );
+        let server = actix_web::rt::spawn(server);
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream
+                .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+
+        sleep(Duration::from_millis(200)).await;
+        signal.send(()).unwrap();
+
+        server.await.unwrap().unwrap();
+        shutdown.await.unwrap();
+
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("done"));
+    }
+}
@@ -0,0 +1,9 @@
+pub mod circuit_breaker;
+pub mod compression;
+pub mod idempotency;
+pub mod internal_secret;
+pub mod maintenance;
+pub mod passkey_rate_limit;
+pub mod real_ip;
+pub mod security_headers;
+pub mod server_timing;
@@ -0,0 +1,234 @@
+use std::{
+    future::{Future, Ready, ready},
+    pin::Pin,
+    rc::Rc,
+    sync::atomic::{AtomicU8, AtomicU32, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    Error,
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    http::StatusCode,
+    web,
+};
+
+use crate::service::ServiceError;
+
+const CLOSED: u8 = 0;
+const OPEN: u8 = 1;
+const HALF_OPEN: u8 = 2;
+
+/// Failures are only evaluated once a window has seen this many requests, so
+/// a single failed request cannot open the circuit.
+const MIN_REQUESTS: u32 = 5;
+const WINDOW: Duration = Duration::from_secs(10);
+
+/// Tracks internal server errors, which is how database failures surface, and
+/// rejects requests while the failure rate is above the threshold.
+pub struct CircuitBreaker {
+    threshold_percent: u32,
+    open_duration: Duration,
+    started_at: Instant,
+    state: AtomicU8,
+    failures: AtomicU32,
+    successes: AtomicU32,
+    window_started_ms: AtomicU64,
+    opened_at_ms: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold_percent: u32, open_duration: Duration) -> Self {
+        Self {
+            threshold_percent,
+            open_duration,
+            started_at: Instant::now(),
+            state: AtomicU8::new(CLOSED),
+            failures: AtomicU32::new(0),
+            successes: AtomicU32::new(0),
+            window_started_ms: AtomicU64::new(0),
+            opened_at_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns whether a request may proceed. Once the open period has passed,
+    /// exactly one caller is let through as the half-open trial; a trial that
+    /// never reports back is replaced after another open period.
+    pub fn allow(&self) -> bool {
+        if self.state.load(Ordering::Acquire) == CLOSED {
+            return true;
+        }
+
+        let now = self.elapsed_ms();
+        let opened_at = self.opened_at_ms.load(Ordering::Acquire);
+        if now - opened_at < self.open_duration.as_millis() as u64 {
+            return false;
+        }
+
+        let claimed = self
+            .opened_at_ms
+            .compare_exchange(opened_at, now, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok();
+        if claimed {
+            self.state.store(HALF_OPEN, Ordering::Release);
+        }
+        claimed
+    }
+
+    pub fn record(&self, success: bool) {
+        let now = self.elapsed_ms();
+
+        if self.state.load(Ordering::Acquire) == HALF_OPEN {
+            if success {
+                self.reset_window(now);
+                self.state.store(CLOSED, Ordering::Release);
+            } else {
+                self.open(now);
+            }
+            return;
+        }
+
+        let window_started = self.window_started_ms.load(Ordering::Acquire);
+        if now - window_started >= WINDOW.as_millis() as u64 {
+            self.reset_window(now);
+        }
+
+        if success {
+            self.successes.fetch_add(1, Ordering::AcqRel);
+            return;
+        }
+
+        let failures = self.failures.fetch_add(1, Ordering::AcqRel) + 1;
+        let total = failures + self.successes.load(Ordering::Acquire);
+        if total >= MIN_REQUESTS && failures * 100 > total * self.threshold_percent {
+            self.open(now);
+        }
+    }
+
+    fn open(&self, now: u64) {
+        self.opened_at_ms.store(now, Ordering::Release);
+        self.state.store(OPEN, Ordering::Release);
+        self.reset_window(now);
+    }
+
+    fn reset_window(&self, now: u64) {
+        self.window_started_ms.store(now, Ordering::Release);
+        self.failures.store(0, Ordering::Release);
+        self.successes.store(0, Ordering::Release);
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.started_at.elapsed().as_millis() as u64
+    }
+}
+
+/// Applies the [`CircuitBreaker`] registered as app data to the wrapped
+/// services. Requests pass through unchanged when none is registered.
+pub struct CircuitBreakerMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for CircuitBreakerMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = CircuitBreakerService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CircuitBreakerService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct CircuitBreakerService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for CircuitBreakerService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let Some(breaker) = req.app_data::<web::Data<CircuitBreaker>>().cloned() else {
+                return Ok(service.call(req).await?.map_into_boxed_body());
+            };
+            if !breaker.allow() {
+                return Ok(req.into_response(ServiceError::service_unavailable()));
+            }
+
+            let response = service.call(req).await;
+            let success = match &response {
+                Ok(response) => response.status() != StatusCode::INTERNAL_SERVER_ERROR,
+                Err(err) => {
+                    err.as_response_error().status_code() != StatusCode::INTERNAL_SERVER_ERROR
+                }
+            };
+            breaker.record(success);
+
+            Ok(response?.map_into_boxed_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    #[test]
+    fn test_opens_above_threshold_and_recovers() {
+        let breaker = CircuitBreaker::new(50, Duration::from_millis(50));
+
+        for _ in 0..MIN_REQUESTS {
+            assert!(breaker.allow());
+            breaker.record(false);
+        }
+        assert!(!breaker.allow());
+
+        sleep(Duration::from_millis(60));
+        assert!(breaker.allow());
+        assert!(!breaker.allow(), "only one half-open trial at a time");
+
+        breaker.record(true);
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn test_stays_closed_below_threshold() {
+        let breaker = CircuitBreaker::new(50, Duration::from_secs(10));
+
+        for success in [true, true, false, true, false, true] {
+            breaker.record(success);
+        }
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn test_failed_trial_reopens() {
+        let breaker = CircuitBreaker::new(50, Duration::from_millis(50));
+        for _ in 0..MIN_REQUESTS {
+            breaker.record(false);
+        }
+
+        sleep(Duration::from_millis(60));
+        assert!(breaker.allow());
+        breaker.record(false);
+        assert!(!breaker.allow());
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    future::{Future, Ready, ready},
+    io::Write,
+    pin::Pin,
+    rc::Rc,
+};
+
+use actix_web::{
+    Error,
+    body::{BodySize, BoxBody, MessageBody, to_bytes},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    error::ErrorInternalServerError,
+    http::{
+        StatusCode,
+        header::{ACCEPT_ENCODING, CONTENT_ENCODING, HeaderMap, HeaderValue, VARY},
+    },
+};
+use flate2::{Compression, write::GzEncoder};
+
+/// Gzips responses at the configured level for clients that accept it.
+/// actix-web's `Compress` always uses the fastest gzip level, so this runs
+/// inside it; `Compress` leaves encoded responses alone and still handles
+/// clients that only accept other encodings.
+pub struct GzipMiddleware {
+    level: Compression,
+}
+
+impl GzipMiddleware {
+    pub fn new(level: Compression) -> Self {
+        Self { level }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for GzipMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = GzipService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(GzipService {
+            service: Rc::new(service),
+            level: self.level,
+        }))
+    }
+}
+
+pub struct GzipService<S> {
+    service: Rc<S>,
+    level: Compression,
+}
+
+impl<S, B> Service<ServiceRequest> for GzipService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let level = self.level;
+
+        Box::pin(async move {
+            let accepts_gzip = accepts_gzip(req.headers());
+            let response = service.call(req).await?;
+
+            let skip = !accepts_gzip
+                || response.headers().contains_key(CONTENT_ENCODING)
+                || matches!(
+                    response.status(),
+                    StatusCode::SWITCHING_PROTOCOLS
+                        | StatusCode::NO_CONTENT
+                        | StatusCode::PARTIAL_CONTENT
+                )
+                || matches!(
+                    response.response().body().size(),
+                    BodySize::None | BodySize::Sized(0)
+                );
+            if skip {
+                return Ok(response.map_into_boxed_body());
+            }
+
+            let (request, response) = response.into_parts();
+            let (mut response, body) = response.into_parts();
+            let body = to_bytes(body)
+                .await
+                .map_err(|err| ErrorInternalServerError(err.into().to_string()))?;
+
+            let mut encoder = GzEncoder::new(Vec::new(), level);
+            let body = encoder
+                .write_all(&body)
+                .and_then(|_| encoder.finish())
+                .map_err(ErrorInternalServerError)?;
+
+            let headers = response.headers_mut();
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+
+            Ok(ServiceResponse::new(
+                request,
+                response.set_body(BoxBody::new(body)),
+            ))
+        })
+    }
+}
+
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            parts
+                .next()
+                .is_some_and(|name| name.eq_ignore_ascii_case("gzip"))
+                && parts.all(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|quality| quality.parse::<f32>().ok())
+                        .is_none_or(|quality| quality > 0.0)
+                })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use actix_web::{App, HttpResponse, test, web};
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    const BODY: &str = "compressible compressible compressible compressible";
+
+    #[actix_web::test]
+    async fn test_gzips_at_the_configured_level() {
+        let app = test::init_service(
+            App::new()
+                .wrap(GzipMiddleware::new(Compression::best()))
+                .route(
+                    "/",
+                    web::get().to(|| async { HttpResponse::Ok().body(BODY) }),
+                )
+                .route("/empty", web::get().to(HttpResponse::NoContent)),
+        )
+        .await;
+        let request = |uri: &str, accept: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .insert_header((ACCEPT_ENCODING, accept))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, request("/", "br, gzip;q=0.8")).await;
+        assert_eq!(response.headers().get(CONTENT_ENCODING).unwrap(), "gzip");
+        assert_eq!(response.headers().get(VARY).unwrap(), "accept-encoding");
+        let body = test::read_body(response).await;
+        let mut expected = GzEncoder::new(Vec::new(), Compression::best());
+        expected.write_all(BODY.as_bytes()).unwrap();
+        assert_eq!(body, expected.finish().unwrap());
+        let mut decoded = String::new();
+        GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, BODY);
+
+        for accept in ["br", "gzip;q=0", "identity"] {
+            let response = test::call_service(&app, request("/", accept)).await;
+            assert!(!response.headers().contains_key(CONTENT_ENCODING));
+            assert_eq!(test::read_body(response).await, BODY);
+        }
+
+        let response = test::call_service(&app, request("/empty", "gzip")).await;
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+    }
+}
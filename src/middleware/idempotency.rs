@@ -0,0 +1,208 @@
+use std::{
+    future::{Future, Ready, ready},
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+};
+
+use actix_web::{
+    Error, HttpResponse,
+    body::{BoxBody, MessageBody, to_bytes},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    error::ErrorInternalServerError,
+    http::{
+        StatusCode,
+        header::{ContentType, HeaderName},
+    },
+    web,
+};
+use chrono::{TimeDelta, Utc};
+use sqlx::PgPool;
+use webauthn_rs::prelude::Uuid;
+
+use crate::{config::AppConfiguration, repository::IdempotencyRepository, service::ServiceError};
+
+const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Replays the stored response for requests that repeat an
+/// `Idempotency-Key`, so client retries cannot apply a change twice.
+/// Requests without the header pass through unchanged.
+pub struct IdempotencyMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for IdempotencyMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = IdempotencyService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct IdempotencyService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let Some(key) = req.headers().get(IDEMPOTENCY_KEY) else {
+                return Ok(service.call(req).await?.map_into_boxed_body());
+            };
+            let Some(key) = key.to_str().ok().and_then(|key| Uuid::parse_str(key).ok()) else {
+                return Ok(req.into_response(ServiceError::invalid_idempotency_key()));
+            };
+
+            let (Some(pool), Some(app_config)) = (
+                req.app_data::<web::ThinData<PgPool>>().cloned(),
+                req.app_data::<web::ThinData<Arc<AppConfiguration>>>()
+                    .cloned(),
+            ) else {
+                return Ok(req.into_response(ServiceError::internal_server_error()));
+            };
+
+            let expires_at =
+                Utc::now() + TimeDelta::seconds(app_config.idempotency_key_ttl_seconds);
+            match IdempotencyRepository::claim(&pool, &key, expires_at).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    let response = match IdempotencyRepository::get(&pool, &key).await {
+                        Ok(Some(stored)) => match (stored.response_status, stored.response_body) {
+                            (Some(status), Some(body)) => replay(status, body),
+                            _ => ServiceError::duplicate_request(),
+                        },
+                        Ok(None) => ServiceError::duplicate_request(),
+                        Err(_) => ServiceError::internal_server_error(),
+                    };
+                    return Ok(req.into_response(response));
+                }
+                Err(_) => return Ok(req.into_response(ServiceError::internal_server_error())),
+            }
+
+            let response = match service.call(req).await {
+                Ok(response) => response,
+                Err(err) => {
+                    let _ = IdempotencyRepository::release(&pool, &key).await;
+                    return Err(err);
+                }
+            };
+
+            let (request, response) = response.into_parts();
+            let (response, body) = response.into_parts();
+            let body = to_bytes(body)
+                .await
+                .map_err(|err| ErrorInternalServerError(err.into().to_string()))?;
+            let status = response.status();
+
+            let stored = if status.is_server_error() {
+                IdempotencyRepository::release(&pool, &key).await
+            } else {
+                IdempotencyRepository::complete(&pool, &key, status.as_u16() as i16, &body).await
+            };
+            if stored.is_err() {
+                return Ok(ServiceResponse::new(
+                    request,
+                    ServiceError::internal_server_error(),
+                ));
+            }
+
+            Ok(ServiceResponse::new(
+                request,
+                response.set_body(BoxBody::new(body)),
+            ))
+        })
+    }
+}
+
+fn replay(status: i16, body: Vec<u8>) -> HttpResponse {
+    let Ok(status) = StatusCode::from_u16(status as u16) else {
+        return ServiceError::internal_server_error();
+    };
+
+    let mut response = HttpResponse::build(status);
+    if !body.is_empty() {
+        response.insert_header(ContentType::json());
+    }
+    response.body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use actix_web::{App, test};
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::test_utils::test_app_config;
+
+    #[sqlx::test]
+    async fn test_repeated_key_replays_response(pool: PgPool) {
+        let calls = web::Data::new(AtomicUsize::new(0));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::ThinData(pool.clone()))
+                .app_data(web::ThinData(Arc::new(test_app_config())))
+                .app_data(calls.clone())
+                .service(web::resource("/finish").wrap(IdempotencyMiddleware).route(
+                    web::post().to(|calls: web::Data<AtomicUsize>| async move {
+                        let call = calls.fetch_add(1, Ordering::SeqCst);
+                        HttpResponse::Created().json(json!({ "call": call }))
+                    }),
+                )),
+        )
+        .await;
+        let request = |key: &str| {
+            test::TestRequest::post()
+                .uri("/finish")
+                .insert_header((IDEMPOTENCY_KEY, key))
+                .to_request()
+        };
+
+        let key = Uuid::new_v4().to_string();
+        for _ in 0..2 {
+            let response = test::call_service(&app, request(&key)).await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let body: Value = test::read_body_json(response).await;
+            assert_eq!(body, json!({ "call": 0 }));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let in_flight = Uuid::new_v4();
+        IdempotencyRepository::claim(&pool, &in_flight, Utc::now() + TimeDelta::minutes(1))
+            .await
+            .unwrap();
+        let response = test::call_service(&app, request(&in_flight.to_string())).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["kind"], "DuplicateRequest");
+
+        let response = test::call_service(&app, request("not-a-uuid")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response =
+            test::call_service(&app, test::TestRequest::post().uri("/finish").to_request()).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
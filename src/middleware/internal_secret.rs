@@ -0,0 +1,123 @@
+use std::{
+    future::{Future, Ready, ready},
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+};
+
+use actix_web::{
+    Error,
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    web,
+};
+
+use crate::{config::AppConfiguration, service::authorize_internal};
+
+/// Only lets requests through that carry the internal secret of the
+/// [`AppConfiguration`] registered as app data as bearer token. Without a
+/// registered configuration every request is rejected.
+pub struct InternalSecretMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for InternalSecretMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = InternalSecretService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(InternalSecretService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct InternalSecretService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for InternalSecretService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let internal_secret = req
+                .app_data::<web::ThinData<Arc<AppConfiguration>>>()
+                .map(|app_config| app_config.internal_secret.as_str())
+                .unwrap_or_default();
+            if let Err(response) = authorize_internal(req.request(), internal_secret) {
+                return Ok(req.into_response(response));
+            }
+
+            Ok(service.call(req).await?.map_into_boxed_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, HttpResponse, http::StatusCode, http::header::AUTHORIZATION, test};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_requires_internal_secret() {
+        let mut app_config = AppConfiguration::default();
+        app_config.internal_secret = "Internal".into();
+        let secret = app_config.internal_secret.clone();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::ThinData(Arc::new(app_config)))
+                .service(
+                    web::scope("/admin")
+                        .wrap(InternalSecretMiddleware)
+                        .default_service(web::to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+        let get = |authorization: Option<String>| {
+            let request = test::TestRequest::get().uri("/admin/audit-log");
+            match authorization {
+                Some(authorization) => request.insert_header((AUTHORIZATION, authorization)),
+                None => request,
+            }
+            .to_request()
+        };
+
+        let response = test::call_service(&app, get(None)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&app, get(Some("Bearer wrong".into()))).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = test::call_service(&app, get(Some(secret.clone()))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&app, get(Some(format!("Bearer {secret}")))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Fails closed when no configuration is registered.
+        let app = test::init_service(
+            App::new().service(
+                web::scope("/admin")
+                    .wrap(InternalSecretMiddleware)
+                    .default_service(web::to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+        let response = test::call_service(&app, get(Some(format!("Bearer {secret}")))).await;
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    future::{Future, Ready, ready},
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use actix_web::{
+    Error,
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+};
+
+use crate::service::ServiceError;
+
+/// Process-wide rather than app data, because actix builds one app per
+/// worker and a toggle has to reach all of them.
+static MAINTENANCE_MODE: AtomicBool = AtomicBool::new(false);
+static MAINTENANCE_MESSAGE: RwLock<String> = RwLock::new(String::new());
+
+/// Routes that stay reachable during maintenance, so probes keep passing.
+/// Operators switch it off again on the internal port, which is not wrapped.
+const EXEMPT_PATHS: [&str; 2] = ["/health", "/ready"];
+
+pub fn set_maintenance_mode(enabled: bool, message: String) {
+    *MAINTENANCE_MESSAGE
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = message;
+    MAINTENANCE_MODE.store(enabled, Ordering::Release);
+}
+
+/// The configured message while maintenance mode is enabled.
+pub fn maintenance_message() -> Option<String> {
+    if !MAINTENANCE_MODE.load(Ordering::Acquire) {
+        return None;
+    }
+    let message = MAINTENANCE_MESSAGE
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    Some(message.clone())
+}
+
+/// Answers every request except [`EXEMPT_PATHS`] with `503 Service
+/// Unavailable` while maintenance mode is enabled.
+pub struct MaintenanceModeMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for MaintenanceModeMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = MaintenanceModeService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(MaintenanceModeService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct MaintenanceModeService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for MaintenanceModeService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            if !EXEMPT_PATHS.contains(&req.path())
+                && let Some(message) = maintenance_message()
+            {
+                return Ok(req.into_response(ServiceError::maintenance(message)));
+            }
+
+            Ok(service.call(req).await?.map_into_boxed_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, HttpResponse, http::StatusCode, test, web};
+    use serde_json::Value;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_maintenance_mode_spares_exempt_paths() {
+        let app = test::init_service(
+            App::new()
+                .wrap(MaintenanceModeMiddleware)
+                .route("/health", web::get().to(HttpResponse::Ok))
+                .route("/api/v1/version", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let get = |uri| test::TestRequest::get().uri(uri).to_request();
+
+        set_maintenance_mode(true, "Database upgrade".into());
+        let response = test::call_service(&app, get("/api/v1/version")).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["kind"], "Maintenance");
+        assert_eq!(body["message"], "Database upgrade");
+        let response = test::call_service(&app, get("/health")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        set_maintenance_mode(false, String::new());
+        let response = test::call_service(&app, get("/api/v1/version")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    future::{Future, Ready, ready},
+    net::IpAddr,
+    pin::Pin,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    Error, HttpMessage,
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    web,
+};
+use dashmap::DashMap;
+
+use crate::{config::PasskeyRateLimits, middleware::real_ip::RealIp, service::ServiceError};
+
+const WINDOW: Duration = Duration::from_secs(60);
+/// Above this many tracked clients, windows that have ended are dropped
+/// before a new client is added.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Ceremony starts are limited separately, everything else under the passkey
+/// scope (finish steps, credential management) is not limited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+impl Ceremony {
+    fn from_path(path: &str) -> Option<Self> {
+        if path.ends_with("/start-registration") {
+            Some(Self::Registration)
+        } else if path.ends_with("/start-authentication")
+            || path.ends_with("/start-discoverable-authentication")
+        {
+            Some(Self::Authentication)
+        } else {
+            None
+        }
+    }
+}
+
+/// Counts ceremony starts per client address in fixed one minute windows.
+/// Registered once as app data, so the limits hold across all workers.
+pub struct PasskeyRateLimiter {
+    limits: PasskeyRateLimits,
+    windows: DashMap<(IpAddr, Ceremony), (Instant, u32)>,
+}
+
+impl PasskeyRateLimiter {
+    pub fn new(limits: PasskeyRateLimits) -> Self {
+        Self {
+            limits,
+            windows: DashMap::new(),
+        }
+    }
+
+    /// Counts a start of `ceremony` by `ip`. Once the limit is reached,
+    /// returns how long until the window ends instead.
+    fn acquire(&self, ip: IpAddr, ceremony: Ceremony) -> Result<(), Duration> {
+        let limit = match ceremony {
+            Ceremony::Registration => self.limits.start_registration_rpm,
+            Ceremony::Authentication => self.limits.start_authentication_rpm,
+        };
+        if limit == 0 {
+            return Ok(());
+        }
+
+        if !self.windows.contains_key(&(ip, ceremony)) && self.windows.len() >= MAX_TRACKED_CLIENTS
+        {
+            self.windows
+                .retain(|_, (started_at, _)| started_at.elapsed() < WINDOW);
+        }
+
+        let mut window = self
+            .windows
+            .entry((ip, ceremony))
+            .or_insert_with(|| (Instant::now(), 0));
+        let (started_at, count) = window.value_mut();
+        if started_at.elapsed() >= WINDOW {
+            *started_at = Instant::now();
+            *count = 0;
+        }
+        if *count >= limit {
+            return Err(WINDOW.saturating_sub(started_at.elapsed()));
+        }
+        *count += 1;
+
+        Ok(())
+    }
+}
+
+/// Applies the [`PasskeyRateLimiter`] registered as app data to the wrapped
+/// services. Requests pass through unchanged when none is registered.
+pub struct PasskeyRateLimiterMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for PasskeyRateLimiterMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = PasskeyRateLimiterService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(PasskeyRateLimiterService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct PasskeyRateLimiterService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for PasskeyRateLimiterService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let limiter = req.app_data::<web::Data<PasskeyRateLimiter>>();
+            let ceremony = Ceremony::from_path(req.path());
+            let ip = req
+                .extensions()
+                .get::<RealIp>()
+                .map(|real_ip| real_ip.0)
+                .or_else(|| req.peer_addr().map(|addr| addr.ip()));
+
+            if let (Some(limiter), Some(ceremony), Some(ip)) = (limiter, ceremony, ip)
+                && let Err(retry_after) = limiter.acquire(ip, ceremony)
+            {
+                return Ok(req.into_response(ServiceError::rate_limited(retry_after)));
+            }
+
+            Ok(service.call(req).await?.map_into_boxed_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        App, HttpResponse,
+        http::{StatusCode, header::RETRY_AFTER},
+        test,
+    };
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_limits_ceremony_starts_per_client() {
+        let limiter = PasskeyRateLimiter::new(PasskeyRateLimits {
+            start_registration_rpm: 3,
+            start_authentication_rpm: 5,
+        });
+        let app = test::init_service(
+            App::new().app_data(web::Data::new(limiter)).service(
+                web::scope("/passkeys")
+                    .wrap(PasskeyRateLimiterMiddleware)
+                    .default_service(web::to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+        let post = |path: &str, ip: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/passkeys/{path}"))
+                .peer_addr(format!("{ip}:4711").parse().unwrap())
+                .to_request()
+        };
+
+        for _ in 0..3 {
+            let response = test::call_service(&app, post("start-registration", "10.0.0.1")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = test::call_service(&app, post("start-registration", "10.0.0.1")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response
+            .headers()
+            .get(RETRY_AFTER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=60).contains(&retry_after));
+
+        // Other clients, other ceremonies and finish steps are not affected.
+        let response = test::call_service(&app, post("start-registration", "10.0.0.2")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        for _ in 0..5 {
+            let response = test::call_service(&app, post("start-authentication", "10.0.0.1")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response =
+            test::call_service(&app, post("start-discoverable-authentication", "10.0.0.1")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        for _ in 0..10 {
+            let response = test::call_service(&app, post("finish-registration", "10.0.0.1")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    future::{Ready, ready},
+    net::IpAddr,
+    rc::Rc,
+};
+
+use actix_web::{
+    Error, HttpMessage, HttpRequest,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    http::header::{HeaderName, X_FORWARDED_FOR},
+};
+
+const CF_CONNECTING_IP: HeaderName = HeaderName::from_static("cf-connecting-ip");
+const X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+
+/// Client address as seen in front of any reverse proxies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RealIp(pub IpAddr);
+
+/// Resolves the client address from `CF-Connecting-IP`, `X-Real-IP` or
+/// `X-Forwarded-For`, falling back to the peer address.
+///
+/// With `trusted_proxy_count` set, that many trailing `X-Forwarded-For`
+/// entries are treated as our own proxy hops and skipped; otherwise the
+/// first entry is used.
+pub fn real_ip(request: &HttpRequest, trusted_proxy_count: usize) -> Option<IpAddr> {
+    let header_ip = |name: &HeaderName| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+    };
+
+    header_ip(&CF_CONNECTING_IP)
+        .or_else(|| header_ip(&X_REAL_IP))
+        .or_else(|| forwarded_for(request, trusted_proxy_count))
+        .or_else(|| request.peer_addr().map(|addr| addr.ip()))
+}
+
+fn forwarded_for(request: &HttpRequest, trusted_proxy_count: usize) -> Option<IpAddr> {
+    let entries: Vec<&str> = request
+        .headers()
+        .get(X_FORWARDED_FOR)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .map(str::trim)
+        .collect();
+
+    let index = match trusted_proxy_count {
+        0 => 0,
+        count => entries.len().saturating_sub(count + 1),
+    };
+    entries.get(index)?.parse().ok()
+}
+
+pub struct RealIpMiddleware {
+    trusted_proxy_count: usize,
+}
+
+impl RealIpMiddleware {
+    pub fn new(trusted_proxy_count: usize) -> Self {
+        Self {
+            trusted_proxy_count,
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RealIpMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RealIpService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RealIpService {
+            service: Rc::new(service),
+            trusted_proxy_count: self.trusted_proxy_count,
+        }))
+    }
+}
+
+pub struct RealIpService<S> {
+    service: Rc<S>,
+    trusted_proxy_count: usize,
+}
+
+impl<S, B> Service<ServiceRequest> for RealIpService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = S::Future;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Some(ip) = real_ip(req.request(), self.trusted_proxy_count) {
+            req.extensions_mut().insert(RealIp(ip));
+        }
+
+        self.service.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use actix_web::{App, HttpResponse, test, web};
+
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        "10.0.0.1:4000".parse().unwrap()
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_cf_connecting_ip_takes_precedence() {
+        let request = test::TestRequest::default()
+            .peer_addr(peer())
+            .insert_header((CF_CONNECTING_IP, "203.0.113.7"))
+            .insert_header((X_REAL_IP, "198.51.100.2"))
+            .insert_header((X_FORWARDED_FOR, "192.0.2.1"))
+            .to_http_request();
+
+        assert_eq!(real_ip(&request, 0), ip("203.0.113.7"));
+    }
+
+    #[actix_web::test]
+    async fn test_x_real_ip() {
+        let request = test::TestRequest::default()
+            .peer_addr(peer())
+            .insert_header((X_REAL_IP, "198.51.100.2"))
+            .insert_header((X_FORWARDED_FOR, "192.0.2.1"))
+            .to_http_request();
+
+        assert_eq!(real_ip(&request, 0), ip("198.51.100.2"));
+    }
+
+    #[actix_web::test]
+    async fn test_x_forwarded_for_first_entry() {
+        let request = test::TestRequest::default()
+            .peer_addr(peer())
+            .insert_header((X_FORWARDED_FOR, "192.0.2.1, 198.51.100.2, 10.0.0.2"))
+            .to_http_request();
+
+        assert_eq!(real_ip(&request, 0), ip("192.0.2.1"));
+    }
+
+    #[actix_web::test]
+    async fn test_x_forwarded_for_skips_trusted_proxies() {
+        let request = test::TestRequest::default()
+            .peer_addr(peer())
+            .insert_header((X_FORWARDED_FOR, "6.6.6.6, 192.0.2.1, 10.0.0.2"))
+            .to_http_request();
+
+        assert_eq!(real_ip(&request, 1), ip("192.0.2.1"));
+        assert_eq!(real_ip(&request, 5), ip("6.6.6.6"));
+    }
+
+    #[actix_web::test]
+    async fn test_peer_addr_fallback() {
+        let request = test::TestRequest::default()
+            .peer_addr(peer())
+            .insert_header((X_FORWARDED_FOR, "not-an-ip"))
+            .to_http_request();
+
+        assert_eq!(real_ip(&request, 0), ip("10.0.0.1"));
+    }
+
+    #[actix_web::test]
+    async fn test_middleware_stores_real_ip() {
+        let app = test::init_service(App::new().wrap(RealIpMiddleware::new(0)).route(
+            "/",
+            web::get().to(|request: HttpRequest| async move {
+                let real_ip = request.extensions().get::<RealIp>().copied();
+                HttpResponse::Ok().body(real_ip.map(|ip| ip.0.to_string()).unwrap_or_default())
+            }),
+        ))
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/")
+            .peer_addr(peer())
+            .insert_header((CF_CONNECTING_IP, "2001:db8::1"))
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert_eq!(body, "2001:db8::1");
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    future::{Future, Ready, ready},
+    pin::Pin,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    Error,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready},
+    http::header::{HeaderName, HeaderValue},
+};
+
+use crate::timing;
+
+const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+
+/// Adds `Server-Timing: total;dur=<ms>` to every response. With details
+/// enabled it also reports the time spent in the database and in password
+/// hashing, which tells callers more than they should know in production.
+pub struct ServerTimingMiddleware {
+    details_enabled: bool,
+}
+
+impl ServerTimingMiddleware {
+    pub fn new(details_enabled: bool) -> Self {
+        Self { details_enabled }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ServerTimingMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ServerTimingService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ServerTimingService {
+            service: Rc::new(service),
+            details_enabled: self.details_enabled,
+        }))
+    }
+}
+
+pub struct ServerTimingService<S> {
+    service: Rc<S>,
+    details_enabled: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for ServerTimingService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let details_enabled = self.details_enabled;
+
+        Box::pin(async move {
+            let started_at = Instant::now();
+            let (response, details) = if details_enabled {
+                let (response, timings) = timing::collect(service.call(req)).await;
+                (response, Some(timings))
+            } else {
+                (service.call(req).await, None)
+            };
+            let mut response = response?;
+
+            let mut value = metric("total", started_at.elapsed());
+            if let Some(timings) = details {
+                value = format!(
+                    "{value}, {}, {}",
+                    metric("db", timings.db()),
+                    metric("hash", timings.hash())
+                );
+            }
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                response.headers_mut().insert(SERVER_TIMING, value);
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+fn metric(name: &str, duration: Duration) -> String {
+    format!("{name};dur={:.1}", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{App, HttpResponse, test, web};
+
+    use super::*;
+    use crate::timing::{Metric, Timer};
+
+    async fn query() -> HttpResponse {
+        let _timer = Timer::start(Metric::Db);
+        HttpResponse::Ok().finish()
+    }
+
+    async fn server_timing(details_enabled: bool) -> String {
+        let app = test::init_service(
+            App::new()
+                .wrap(ServerTimingMiddleware::new(details_enabled))
+                .route("/", web::get().to(query)),
+        )
+        .await;
+        let response = test::call_service(&app, test::TestRequest::get().to_request()).await;
+        response
+            .headers()
+            .get(SERVER_TIMING)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[actix_web::test]
+    async fn test_details_only_when_enabled() {
+        let value = server_timing(false).await;
+        assert!(value.starts_with("total;dur="));
+        assert!(!value.contains("db;dur="));
+
+        let value = server_timing(true).await;
+        let metrics: Vec<_> = value
+            .split(", ")
+            .map(|metric| metric.split_once(";dur=").unwrap().0)
+            .collect();
+        assert_eq!(metrics, ["total", "db", "hash"]);
+    }
+}
@@ -1,20 +1,23 @@
-use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::IpAddr};
+
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use serde::Serialize;
 use serde_json::{Value, to_value};
-use sqlx::{
-    PgPool, query_file, query_file_as,
-    types::{Json, JsonRawValue},
-};
-use webauthn_rs::prelude::{CredentialID, Passkey, PasskeyRegistration, Uuid};
+use sqlx::{PgExecutor, PgPool, PgTransaction, query_file, query_file_as};
+use webauthn_rs::prelude::{CredentialID, Passkey, Uuid};
 
 use crate::{
-    crypto::{Method, PasswordHandler},
+    crypto::{Method, PasswordHandlerTrait},
     error::Error,
+    timing::{Metric, Timer},
 };
 
 pub struct Repository;
 
 impl Repository {
     pub async fn get_by_mail(pool: &PgPool, email: &str) -> Result<Option<User>, Error> {
+        let _timer = Timer::start(Metric::Db);
         let record = query_file_as!(User, "queries/get-user-by-mail.sql", email)
             .fetch_one(pool)
             .await;
@@ -26,24 +29,125 @@ impl Repository {
         }
     }
 
-    pub async fn get_credentials(
+    pub async fn get_user_by_id(pool: &PgPool, user_id: i64) -> Result<Option<User>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file_as!(User, "queries/get-user-by-id.sql", user_id)
+            .fetch_one(pool)
+            .await;
+
+        match record {
+            Ok(user) => Ok(Some(user)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// One page of accounts with an id above `cursor`. `query` restricts
+    /// the page to mails or names starting with it.
+    pub async fn get_credentials_filtered(
+        pool: &PgPool,
+        query: Option<&str>,
+        page_size: i64,
+        cursor: i64,
+    ) -> Result<Vec<User>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let records = match query {
+            Some(query) => {
+                query_file_as!(
+                    User,
+                    "queries/get-user-credentials-filtered.sql",
+                    cursor,
+                    format!("{}%", escape_like(query)),
+                    page_size
+                )
+                .fetch_all(pool)
+                .await
+            }
+            None => {
+                query_file_as!(User, "queries/get-user-credentials.sql", cursor, page_size)
+                    .fetch_all(pool)
+                    .await
+            }
+        };
+
+        Ok(records?)
+    }
+
+    pub async fn count_credentials(pool: &PgPool) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/count-user-credentials.sql")
+            .fetch_one(pool)
+            .await?;
+
+        Ok(record.count)
+    }
+
+    pub async fn search_users(
         pool: &PgPool,
+        query: &str,
         page: i64,
         page_size: i64,
     ) -> Result<Vec<User>, Error> {
-        let records = query_file_as!(
-            User,
-            "queries/get-user-credentials.sql",
-            page_size,
-            page * page_size
-        )
-        .fetch_all(pool)
-        .await;
+        let _timer = Timer::start(Metric::Db);
+        let records = if is_short_search(query) {
+            query_file_as!(
+                User,
+                "queries/search-users-like.sql",
+                escape_like(query),
+                page_size,
+                page * page_size
+            )
+            .fetch_all(pool)
+            .await
+        } else {
+            query_file_as!(
+                User,
+                "queries/search-users.sql",
+                query,
+                page_size,
+                page * page_size
+            )
+            .fetch_all(pool)
+            .await
+        };
 
         Ok(records?)
     }
 
-    pub async fn create_user(pool: &PgPool, user: UserDTO<'_>) -> Result<i64, Error> {
+    pub async fn count_search_users(pool: &PgPool, query: &str) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let count = if is_short_search(query) {
+            query_file!("queries/count-search-users-like.sql", escape_like(query))
+                .fetch_one(pool)
+                .await?
+                .count
+        } else {
+            query_file!("queries/count-search-users.sql", query)
+                .fetch_one(pool)
+                .await?
+                .count
+        };
+
+        Ok(count)
+    }
+
+    pub async fn create_user_in_transaction(
+        pool: &PgPool,
+        user: UserDTO<'_>,
+    ) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let mut transaction = pool.begin().await?;
+        let user_id = Self::create_user(&mut transaction, user).await?;
+        transaction.commit().await?;
+
+        Ok(user_id)
+    }
+
+    pub async fn create_user(
+        transaction: &mut PgTransaction<'_>,
+        user: UserDTO<'_>,
+    ) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
         let record = query_file!(
             "queries/create-user.sql",
             user.name,
@@ -54,11 +158,164 @@ impl Repository {
             user.password_peppered,
             user.password_salted_and_peppered
         )
-        .fetch_one(pool)
+        .fetch_optional(&mut **transaction)
         .await?;
 
-        Ok(record.id)
+        record.map(|record| record.id).ok_or(Error::UniqueViolation)
+    }
+
+    pub async fn update_password_hash(
+        pool: &PgPool,
+        user_id: i64,
+        password_hash: &str,
+    ) -> Result<(), Error> {
+        let _timer = Timer::start(Metric::Db);
+        let _res = query_file!("queries/update-password-hash.sql", user_id, password_hash)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_locked(
+        pool: &PgPool,
+        user_id: i64,
+        locked: bool,
+    ) -> Result<Option<bool>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/update-account-lock.sql", user_id, locked)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(record.map(|record| record.locked_by_admin))
+    }
+
+    /// Marks an account as deleted and overwrites its personal data, returning
+    /// whether it existed. The row stays until [`Self::purge_deleted_users`],
+    /// so audit events keep resolving to a user id. Linked passkey users are
+    /// deleted along with it.
+    pub async fn soft_delete_user(pool: &PgPool, user_id: i64) -> Result<bool, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let mut transaction = pool.begin().await?;
+        let record = query_file!("queries/soft-delete-user.sql", user_id)
+            .fetch_optional(&mut *transaction)
+            .await?;
+        if record.is_none() {
+            return Ok(false);
+        }
+        query_file!("queries/passkey/soft-delete-linked-users.sql", user_id)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Permanently removes accounts and passkey users deleted before
+    /// `before`, returning how many accounts were removed.
+    pub async fn purge_deleted_users(pool: &PgPool, before: DateTime<Utc>) -> Result<u64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let mut transaction = pool.begin().await?;
+        query_file!("queries/passkey/purge-deleted-user-credentials.sql", before)
+            .execute(&mut *transaction)
+            .await?;
+        query_file!("queries/passkey/purge-deleted-users.sql", before)
+            .execute(&mut *transaction)
+            .await?;
+        let result = query_file!("queries/purge-deleted-users.sql", before)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_user_by_id_admin(
+        pool: &PgPool,
+        user_id: i64,
+    ) -> Result<Option<AdminUserDetail>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file_as!(AdminUserDetail, "queries/get-user-detail.sql", user_id)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(record)
     }
+
+    pub async fn update_user_role(
+        pool: &PgPool,
+        user_id: i64,
+        role: &str,
+    ) -> Result<Option<String>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/update-user-role.sql", user_id, role)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(record.map(|record| record.previous_role))
+    }
+
+    pub async fn is_locked_by_mail(pool: &PgPool, email: &str) -> Result<bool, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/get-account-lock-by-mail.sql", email)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(record.is_some_and(|record| record.locked_by_admin))
+    }
+
+    pub async fn schema_version(pool: &PgPool) -> Result<Option<i64>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/get-schema-version.sql")
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(record.map(|record| record.version))
+    }
+
+    /// Counts password and passkey users created in `[from, to)`.
+    pub async fn count_users_registered_between(
+        pool: &PgPool,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/count-users-registered-between.sql", from, to)
+            .fetch_one(pool)
+            .await?;
+
+        Ok(record.count)
+    }
+
+    pub async fn count_users_by_role(pool: &PgPool) -> Result<HashMap<String, i64>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let records = query_file!("queries/count-users-by-role.sql")
+            .fetch_all(pool)
+            .await?;
+
+        Ok(records
+            .into_iter()
+            .map(|record| (record.role, record.count))
+            .collect())
+    }
+
+    pub async fn ping(pool: &PgPool) -> Result<(), Error> {
+        let _timer = Timer::start(Metric::Db);
+        let _record = query_file!("queries/ping.sql").fetch_one(pool).await?;
+
+        Ok(())
+    }
+}
+
+fn is_short_search(query: &str) -> bool {
+    query.chars().count() < 3
+}
+
+fn escape_like(query: &str) -> String {
+    query
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
 }
 
 pub struct UserDTO<'a> {
@@ -76,7 +333,7 @@ impl<'a> UserDTO<'a> {
         email: &'a str,
         name: &'a str,
         password: &'a str,
-        handler: &PasswordHandler,
+        handler: &dyn PasswordHandlerTrait,
     ) -> Self {
         Self {
             email,
@@ -85,7 +342,7 @@ impl<'a> UserDTO<'a> {
             password_hashed: handler.hash(password, Method::Hash),
             password_salted: handler.hash(password, Method::Salt),
             password_peppered: handler.hash(password, Method::Pepper),
-            password_salted_and_peppered: handler.hash(password, Method::SaltPepper),
+            password_salted_and_peppered: handler.hash(password, handler.hash_method()),
         }
     }
 }
@@ -100,18 +357,81 @@ pub struct User {
     password_salted: String,
     password_peppered: String,
     password_salted_and_peppered: String,
+    locked_by_admin: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
 }
 
 impl User {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn password_hash(&self) -> &str {
         &self.password_salted_and_peppered
     }
+
+    /// Whether the account may sign in.
+    pub fn is_active(&self) -> bool {
+        self.status() == UserStatus::Active
+    }
+
+    pub fn status(&self) -> UserStatus {
+        UserStatus::from_lock(self.locked_by_admin)
+    }
+}
+
+/// Account state as reported by the API. Only states the schema can record
+/// are represented; there is no temporary lockout or mail verification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserStatus {
+    Active,
+    LockedByAdmin,
+}
+
+impl UserStatus {
+    fn from_lock(locked_by_admin: bool) -> Self {
+        if locked_by_admin {
+            Self::LockedByAdmin
+        } else {
+            Self::Active
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AdminUserDetail {
+    id: i64,
+    email: String,
+    name: String,
+    role: String,
+    locked_by_admin: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    passkey_count: i64,
+    last_login_at: Option<DateTime<Utc>>,
+}
+
+impl AdminUserDetail {
+    pub fn status(&self) -> UserStatus {
+        UserStatus::from_lock(self.locked_by_admin)
+    }
 }
 
 pub struct PasskeyRepository;
 
 impl PasskeyRepository {
     pub async fn get_user_by_mail(pool: &PgPool, mail: &str) -> Result<Option<PasskeyUser>, Error> {
+        let _timer = Timer::start(Metric::Db);
         let record = query_file_as!(PasskeyUser, "queries/passkey/get-user-by-mail.sql", mail)
             .fetch_one(pool)
             .await;
@@ -127,6 +447,7 @@ impl PasskeyRepository {
         pool: &PgPool,
         user_id: &Uuid,
     ) -> Result<Option<PasskeyUser>, Error> {
+        let _timer = Timer::start(Metric::Db);
         let record = query_file_as!(PasskeyUser, "queries/passkey/get-user-by-id.sql", user_id)
             .fetch_one(pool)
             .await;
@@ -138,12 +459,37 @@ impl PasskeyRepository {
         }
     }
 
-    pub async fn create_user(pool: &PgPool, user: &PasskeyUser) -> Result<(), Error> {
+    pub async fn create_user(
+        executor: impl PgExecutor<'_>,
+        user: &PasskeyUser,
+    ) -> Result<(), Error> {
+        let _timer = Timer::start(Metric::Db);
         let _record = query_file!(
             "queries/passkey/create-user.sql",
             user.id,
             user.mail,
-            user.name
+            user.name,
+            user.created_at,
+            user.updated_at
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records that the passkey user and the password account belong to the
+    /// same person. Linking an already linked pair is a no-op.
+    pub async fn link_existing_user(
+        pool: &PgPool,
+        passkey_user_id: &Uuid,
+        password_user_id: i64,
+    ) -> Result<(), Error> {
+        let _timer = Timer::start(Metric::Db);
+        let _record = query_file!(
+            "queries/passkey/link-account.sql",
+            passkey_user_id,
+            password_user_id
         )
         .execute(pool)
         .await?;
@@ -155,6 +501,7 @@ impl PasskeyRepository {
         pool: &PgPool,
         user_id: &Uuid,
     ) -> Result<Vec<CredentialID>, Error> {
+        let _timer = Timer::start(Metric::Db);
         let records = query_file_as!(
             CredentialIDWrapper,
             "queries/passkey/get-user-credential-ids-by-user-id.sql",
@@ -173,55 +520,182 @@ impl PasskeyRepository {
         pool: &PgPool,
         user_id: &Uuid,
     ) -> Result<Vec<Passkey>, Error> {
+        let _timer = Timer::start(Metric::Db);
         let records = query_file!("queries/passkey/get-user-credentials.sql", user_id)
             .fetch_all(pool)
             .await?;
         Ok(records
             .into_iter()
-            .filter_map(|record| {
-                let value = record.credential;
-                Some(serde_json::from_value::<Passkey>(value).ok()?)
-            })
+            .filter_map(|record| serde_json::from_value::<Passkey>(record.credential).ok())
             .collect())
     }
 
-    pub async fn get_user_credential(
+    pub async fn get_credential_by_id(
         pool: &PgPool,
-        user_id: &Uuid,
-        passkey_id: &[u8],
-    ) -> Result<Option<Passkey>, Error> {
-        let record = query_file!(
-            "queries/passkey/get-user-credential.sql",
-            user_id,
-            passkey_id
-        )
-        .fetch_one(pool)
-        .await;
+        credential_id: &[u8],
+    ) -> Result<Option<PasskeyCredential>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/passkey/get-credential-by-id.sql", credential_id)
+            .fetch_one(pool)
+            .await;
 
         match record {
-            Ok(record) => Ok(Some(serde_json::from_value::<Passkey>(record.credential)?)),
+            Ok(record) => Ok(Some(PasskeyCredential {
+                credential_id: record.credential_id,
+                user_id: record.user_id,
+                credential: serde_json::from_value::<Passkey>(record.credential)?,
+                name: record.name,
+                created_at: record.created_at,
+                last_used_at: record.last_used_at,
+            })),
             Err(sqlx::Error::RowNotFound) => Ok(None),
             Err(err) => Err(err.into()),
         }
     }
 
-    pub async fn create_user_credentials(
+    pub async fn create_user_credentials_in_transaction(
         pool: &PgPool,
+        new_user: Option<&PasskeyUser>,
         user_id: &Uuid,
         passkey: &Passkey,
     ) -> Result<(), Error> {
+        let _timer = Timer::start(Metric::Db);
+        let mut transaction = pool.begin().await?;
+        if let Some(user) = new_user {
+            Self::create_user(&mut *transaction, user).await?;
+        }
+        Self::create_user_credentials(&mut *transaction, user_id, passkey).await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn create_user_credentials(
+        executor: impl PgExecutor<'_>,
+        user_id: &Uuid,
+        passkey: &Passkey,
+    ) -> Result<(), Error> {
+        let _timer = Timer::start(Metric::Db);
         let passkey_json = to_value(passkey).expect("Must be parseable");
-        let _res = query_file!(
+        let result = query_file!(
             "queries/passkey/create-user-credentials.sql",
             passkey.cred_id().as_slice(),
             user_id,
             passkey_json,
         )
-        .execute(pool)
+        .execute(executor)
         .await?;
 
+        if result.rows_affected() == 0 {
+            return Err(Error::UniqueViolation);
+        }
         Ok(())
     }
+
+    pub async fn count_credentials(pool: &PgPool, user_id: &Uuid) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/passkey/count-user-credentials.sql", user_id)
+            .fetch_one(pool)
+            .await?;
+
+        Ok(record.count)
+    }
+
+    pub async fn has_credentials(pool: &PgPool, mail: &str) -> Result<bool, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/passkey/has-credentials-by-mail.sql", mail)
+            .fetch_one(pool)
+            .await?;
+
+        Ok(record.exists)
+    }
+
+    pub async fn count_all_credentials(pool: &PgPool) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/passkey/count-credentials.sql")
+            .fetch_one(pool)
+            .await?;
+
+        Ok(record.count)
+    }
+
+    pub async fn count_users(pool: &PgPool) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/passkey/count-users.sql")
+            .fetch_one(pool)
+            .await?;
+
+        Ok(record.count)
+    }
+
+    pub async fn count_users_with_passkeys(pool: &PgPool) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/passkey/count-users-with-passkeys.sql")
+            .fetch_one(pool)
+            .await?;
+
+        Ok(record.count)
+    }
+
+    pub async fn count_users_without_credentials(pool: &PgPool) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/passkey/count-users-without-credentials.sql")
+            .fetch_one(pool)
+            .await?;
+
+        Ok(record.count)
+    }
+
+    /// Credentials whose user row is gone. The foreign key should keep this
+    /// at zero, anything else points at manual edits or a broken migration.
+    pub async fn count_orphan_credentials(pool: &PgPool) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/passkey/count-orphan-credentials.sql")
+            .fetch_one(pool)
+            .await?;
+
+        Ok(record.count)
+    }
+
+    /// Replaces the stored passkey, e.g. after authentication bumped its
+    /// counter or backup state.
+    pub async fn update_credential(
+        pool: &PgPool,
+        credential_id: &[u8],
+        passkey: &Passkey,
+    ) -> Result<(), Error> {
+        let _timer = Timer::start(Metric::Db);
+        let passkey_json = to_value(passkey)?;
+        let record = query_file!(
+            "queries/passkey/update-user-credential.sql",
+            passkey_json,
+            credential_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        match record {
+            Some(_) => Ok(()),
+            None => Err(Error::Other("credential not found".into())),
+        }
+    }
+
+    pub async fn delete_credential(
+        pool: &PgPool,
+        credential_id: &[u8],
+        user_id: &Uuid,
+    ) -> Result<bool, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!(
+            "queries/passkey/delete-user-credential.sql",
+            credential_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(record.is_some())
+    }
 }
 
 #[derive(Serialize)]
@@ -229,18 +703,856 @@ pub struct PasskeyUser {
     pub(crate) id: Uuid,
     pub(crate) mail: String,
     pub(crate) name: String,
+    pub(crate) created_at: DateTime<Utc>,
+    pub(crate) updated_at: DateTime<Utc>,
 }
 
 impl PasskeyUser {
     pub fn id(&self) -> &Uuid {
         &self.id
     }
+
+    pub fn mail(&self) -> &str {
+        &self.mail
+    }
+}
+
+#[allow(dead_code)]
+pub struct PasskeyCredential {
+    pub(crate) credential_id: Vec<u8>,
+    pub(crate) user_id: Uuid,
+    pub(crate) credential: Passkey,
+    pub(crate) name: Option<String>,
+    pub(crate) created_at: DateTime<Utc>,
+    pub(crate) last_used_at: Option<DateTime<Utc>>,
 }
 
 struct CredentialIDWrapper {
     credential_id: CredentialID,
 }
 
-struct PasskeyWrapper {
-    credential: sqlx::types::Json<Passkey>,
+pub struct AuditRepository;
+
+impl AuditRepository {
+    pub async fn log_event(pool: &PgPool, event: &AuditEventDTO) -> Result<(), Error> {
+        let _timer = Timer::start(Metric::Db);
+        let _res = query_file!(
+            "queries/audit/create-event.sql",
+            event.user_id,
+            event.action.as_str(),
+            event.ip_address.map(IpNet::from),
+            event.user_agent,
+            event.metadata,
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn query_events(
+        pool: &PgPool,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        cursor: i64,
+        page_size: i64,
+    ) -> Result<Vec<AuditEvent>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let records = query_file_as!(
+            AuditEvent,
+            "queries/audit/query-events.sql",
+            from,
+            to,
+            cursor,
+            page_size
+        )
+        .fetch_all(pool)
+        .await;
+
+        Ok(records?)
+    }
+
+    pub async fn count_events(
+        pool: &PgPool,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file!("queries/audit/count-events-in-range.sql", from, to)
+            .fetch_one(pool)
+            .await?;
+
+        Ok(record.count)
+    }
+
+    pub async fn count_actions_since(
+        pool: &PgPool,
+        actions: &[AuditAction],
+        since: DateTime<Utc>,
+    ) -> Result<i64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let actions: Vec<String> = actions
+            .iter()
+            .map(|action| action.as_str().to_owned())
+            .collect();
+        let record = query_file!("queries/audit/count-actions-since.sql", &actions, since)
+            .fetch_one(pool)
+            .await?;
+
+        Ok(record.count)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum AuditAction {
+    SignUp,
+    SignInSuccess,
+    SignInFailure,
+    PasskeyRegistered,
+    PasskeySignInSuccess,
+    PasskeySignInFailure,
+    PasskeyDeleted,
+    AccountLocked,
+    AccountUnlocked,
+    RoleChanged,
+    UserInvited,
+    MaintenanceChanged,
+    UserDeletionRequested,
+    UserDeleted,
+    ChallengeStoreCleared,
+    RateLimitExceededEmail,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::SignUp => "sign_up",
+            AuditAction::SignInSuccess => "sign_in_success",
+            AuditAction::SignInFailure => "sign_in_failure",
+            AuditAction::PasskeyRegistered => "passkey_registered",
+            AuditAction::PasskeySignInSuccess => "passkey_sign_in_success",
+            AuditAction::PasskeySignInFailure => "passkey_sign_in_failure",
+            AuditAction::PasskeyDeleted => "passkey_deleted",
+            AuditAction::AccountLocked => "account_locked",
+            AuditAction::AccountUnlocked => "account_unlocked",
+            AuditAction::RoleChanged => "role_changed",
+            AuditAction::UserInvited => "user_invited",
+            AuditAction::MaintenanceChanged => "maintenance_changed",
+            AuditAction::UserDeletionRequested => "user_deletion_requested",
+            AuditAction::UserDeleted => "user_deleted",
+            AuditAction::ChallengeStoreCleared => "admin_challenge_store_cleared",
+            AuditAction::RateLimitExceededEmail => "rate_limit_exceeded_email",
+        }
+    }
+}
+
+pub struct AuditEventDTO {
+    pub(crate) user_id: Option<i64>,
+    pub(crate) action: AuditAction,
+    pub(crate) ip_address: Option<IpAddr>,
+    pub(crate) user_agent: Option<String>,
+    pub(crate) metadata: Value,
+}
+
+#[derive(Serialize)]
+pub struct AuditEvent {
+    id: i64,
+    user_id: Option<i64>,
+    /// `[DELETED USER]` once the account has been deleted.
+    user_name: Option<String>,
+    action: String,
+    ip_address: Option<IpAddr>,
+    user_agent: Option<String>,
+    metadata: Value,
+    created_at: DateTime<Utc>,
+}
+
+impl AuditEvent {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+}
+
+pub struct IdempotencyRepository;
+
+impl IdempotencyRepository {
+    /// Reserves `key` for a new request. Returns `false` when an unexpired
+    /// request with the same key already exists.
+    pub async fn claim(
+        pool: &PgPool,
+        key: &Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let result = query_file!("queries/idempotency/claim-key.sql", key, expires_at)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn get(pool: &PgPool, key: &Uuid) -> Result<Option<IdempotentResponse>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let record = query_file_as!(IdempotentResponse, "queries/idempotency/get-key.sql", key)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(record)
+    }
+
+    pub async fn complete(
+        pool: &PgPool,
+        key: &Uuid,
+        response_status: i16,
+        response_body: &[u8],
+    ) -> Result<(), Error> {
+        let _timer = Timer::start(Metric::Db);
+        let _res = query_file!(
+            "queries/idempotency/complete-key.sql",
+            key,
+            response_status,
+            response_body
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn release(pool: &PgPool, key: &Uuid) -> Result<(), Error> {
+        let _timer = Timer::start(Metric::Db);
+        let _res = query_file!("queries/idempotency/delete-key.sql", key)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn purge_expired(pool: &PgPool) -> Result<u64, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let result = query_file!("queries/idempotency/purge-expired-keys.sql")
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Stored outcome of an idempotent request; both fields stay empty while
+/// the original request is still in flight.
+pub struct IdempotentResponse {
+    pub response_status: Option<i16>,
+    pub response_body: Option<Vec<u8>>,
+}
+
+pub struct InvitationRepository;
+
+impl InvitationRepository {
+    pub async fn create(
+        pool: &PgPool,
+        token_hash: &[u8],
+        email: &str,
+        role: &str,
+        invited_by: Option<i64>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Invitation, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let invitation = query_file_as!(
+            Invitation,
+            "queries/invitation/create-invitation.sql",
+            token_hash,
+            email,
+            role,
+            invited_by,
+            expires_at
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(invitation)
+    }
+
+    pub async fn list_pending(pool: &PgPool) -> Result<Vec<Invitation>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let invitations = query_file_as!(
+            Invitation,
+            "queries/invitation/list-pending-invitations.sql"
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(invitations)
+    }
+
+    /// Marks a pending invitation as used and creates its account with the
+    /// invited role. Returns `None` for unknown, used or expired tokens; if
+    /// the account cannot be created the invitation stays pending.
+    pub async fn accept(
+        pool: &PgPool,
+        token_hash: &[u8],
+        name: &str,
+        password: &str,
+        handler: &dyn PasswordHandlerTrait,
+    ) -> Result<Option<AcceptedInvitation>, Error> {
+        let _timer = Timer::start(Metric::Db);
+        let mut transaction = pool.begin().await?;
+        let Some(invitation) = query_file!("queries/invitation/use-invitation.sql", token_hash)
+            .fetch_optional(&mut *transaction)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let user_id = Repository::create_user(
+            &mut transaction,
+            UserDTO::new(&invitation.email, name, password, handler),
+        )
+        .await?;
+        query_file!("queries/update-user-role.sql", user_id, invitation.role)
+            .fetch_one(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+
+        Ok(Some(AcceptedInvitation {
+            user_id,
+            role: invitation.role,
+        }))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Invitation {
+    id: i64,
+    email: String,
+    role: String,
+    invited_by: Option<i64>,
+    expires_at: DateTime<Utc>,
+    created_at: DateTime<Utc>,
+}
+
+pub struct AcceptedInvitation {
+    pub user_id: i64,
+    pub role: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{crypto::PasswordHandler, test_utils::test_password_handler};
+
+    include!(concat!(env!("OUT_DIR"), "/query_files.rs"));
+
+    fn password_handler() -> PasswordHandler {
+        test_password_handler()
+    }
+
+    fn account<'a>(email: &'a str, handler: &PasswordHandler) -> UserDTO<'a> {
+        UserDTO::new(email, "Test User", "password", handler)
+    }
+
+    fn passkey_user(mail: &str) -> PasskeyUser {
+        PasskeyUser {
+            id: Uuid::new_v4(),
+            mail: mail.into(),
+            name: "Test User".into(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn passkey(credential_id: &[u8]) -> Passkey {
+        let coordinate = [0u8; 32];
+        serde_json::from_value(json!({
+            "cred": {
+                "cred_id": credential_id,
+                "cred": {
+                    "type_": "ES256",
+                    "key": {
+                        "EC_EC2": {
+                            "curve": "SECP256R1",
+                            "x": coordinate,
+                            "y": coordinate,
+                        }
+                    }
+                },
+                "counter": 0,
+                "transports": null,
+                "user_verified": true,
+                "backup_eligible": false,
+                "backup_state": false,
+                "registration_policy": "required",
+                "extensions": {
+                    "cred_protect": "NotRequested",
+                    "hmac_create_secret": "NotRequested",
+                    "appid": "NotRequested",
+                    "cred_props": "NotRequested"
+                },
+                "attestation": {
+                    "data": "None",
+                    "metadata": "None"
+                },
+                "attestation_format": "none"
+            }
+        }))
+        .expect("Passkey fixture must deserialize")
+    }
+
+    #[sqlx::test]
+    async fn test_create_and_get_user(pool: PgPool) {
+        let handler = password_handler();
+        let user_id =
+            Repository::create_user_in_transaction(&pool, account("alice@example.com", &handler))
+                .await
+                .unwrap();
+
+        let user = Repository::get_by_mail(&pool, "alice@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.id(), user_id);
+        assert_eq!(user.status(), UserStatus::Active);
+        assert_eq!(
+            PasswordHandler::detect_method(user.password_hash()),
+            Some(Method::Argon2id)
+        );
+        assert!(handler.is_hash_of("password", user.password_hash(), Method::Argon2id));
+    }
+
+    #[sqlx::test]
+    async fn test_updated_at_changes_on_update(pool: PgPool) {
+        let handler = password_handler();
+        let user_id =
+            Repository::create_user_in_transaction(&pool, account("grace@example.com", &handler))
+                .await
+                .unwrap();
+        let created = Repository::get_by_mail(&pool, "grace@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(created.created_at, created.updated_at);
+
+        Repository::set_locked(&pool, user_id, true).await.unwrap();
+
+        let updated = Repository::get_by_mail(&pool, "grace@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.created_at, created.created_at);
+        assert!(updated.updated_at > created.updated_at);
+        assert_eq!(updated.status(), UserStatus::LockedByAdmin);
+        assert!(!updated.is_active());
+    }
+
+    #[sqlx::test]
+    async fn test_get_by_mail_not_found(pool: PgPool) {
+        let user = Repository::get_by_mail(&pool, "nobody@example.com")
+            .await
+            .unwrap();
+
+        assert!(user.is_none());
+    }
+
+    #[sqlx::test]
+    async fn test_get_user_by_id(pool: PgPool) {
+        let handler = password_handler();
+        let user_id =
+            Repository::create_user_in_transaction(&pool, account("frank@example.com", &handler))
+                .await
+                .unwrap();
+
+        let user = Repository::get_user_by_id(&pool, user_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.email(), "frank@example.com");
+        assert!(
+            Repository::get_user_by_id(&pool, user_id + 1)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_soft_delete_user(pool: PgPool) {
+        let handler = password_handler();
+        let user_id =
+            Repository::create_user_in_transaction(&pool, account("erin@example.com", &handler))
+                .await
+                .unwrap();
+        let event = AuditEventDTO {
+            user_id: Some(user_id),
+            action: AuditAction::SignUp,
+            ip_address: None,
+            user_agent: None,
+            metadata: json!({}),
+        };
+        AuditRepository::log_event(&pool, &event).await.unwrap();
+        let user_names = || async {
+            let now = Utc::now();
+            AuditRepository::query_events(&pool, now - TimeDelta::hours(1), now, 0, 10)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|event| event.user_name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(user_names().await, [Some("Test User".to_owned())]);
+
+        assert!(Repository::soft_delete_user(&pool, user_id).await.unwrap());
+        assert!(!Repository::soft_delete_user(&pool, user_id).await.unwrap());
+        assert!(
+            Repository::get_by_mail(&pool, "erin@example.com")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            Repository::get_user_by_id(&pool, user_id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(user_names().await, [Some("[DELETED USER]".to_owned())]);
+
+        let now = Utc::now();
+        let purged = Repository::purge_deleted_users(&pool, now - TimeDelta::days(1));
+        assert_eq!(purged.await.unwrap(), 0);
+        let purged = Repository::purge_deleted_users(&pool, now + TimeDelta::seconds(1));
+        assert_eq!(purged.await.unwrap(), 1);
+        assert_eq!(user_names().await, [Some("[DELETED USER]".to_owned())]);
+    }
+
+    #[sqlx::test]
+    async fn test_count_users_by_role_and_registration(pool: PgPool) {
+        let handler = password_handler();
+        for mail in ["grace@example.com", "heidi@example.com"] {
+            Repository::create_user_in_transaction(&pool, account(mail, &handler))
+                .await
+                .unwrap();
+        }
+        let admin_id =
+            Repository::create_user_in_transaction(&pool, account("ivan@example.com", &handler))
+                .await
+                .unwrap();
+        Repository::update_user_role(&pool, admin_id, "admin")
+            .await
+            .unwrap();
+
+        let by_role = Repository::count_users_by_role(&pool).await.unwrap();
+        assert_eq!(
+            by_role,
+            HashMap::from([("admin".into(), 1), ("user".into(), 2)])
+        );
+
+        let now = Utc::now();
+        let count = |from, to| Repository::count_users_registered_between(&pool, from, to);
+        assert_eq!(
+            count(now - TimeDelta::hours(1), now + TimeDelta::hours(1))
+                .await
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            count(now + TimeDelta::hours(1), now + TimeDelta::hours(2))
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_unique_email_violation(pool: PgPool) {
+        let handler = password_handler();
+        Repository::create_user_in_transaction(&pool, account("bob@example.com", &handler))
+            .await
+            .unwrap();
+
+        let err =
+            Repository::create_user_in_transaction(&pool, account("bob@example.com", &handler))
+                .await
+                .unwrap_err();
+        assert!(matches!(err, Error::UniqueViolation));
+    }
+
+    #[sqlx::test]
+    async fn test_create_passkey_user_and_credentials(pool: PgPool) {
+        let user = passkey_user("carol@example.com");
+        PasskeyRepository::create_user_credentials_in_transaction(
+            &pool,
+            Some(&user),
+            user.id(),
+            &passkey(&[1, 2, 3, 4]),
+        )
+        .await
+        .unwrap();
+
+        let stored = PasskeyRepository::get_user_by_mail(&pool, "carol@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.id(), user.id());
+        assert_eq!(
+            PasskeyRepository::count_credentials(&pool, user.id())
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_link_existing_user(pool: PgPool) {
+        let handler = password_handler();
+        let account_id =
+            Repository::create_user_in_transaction(&pool, account("erin@example.com", &handler))
+                .await
+                .unwrap();
+        let user = passkey_user("erin@example.com");
+        PasskeyRepository::create_user(&pool, &user).await.unwrap();
+
+        PasskeyRepository::link_existing_user(&pool, user.id(), account_id)
+            .await
+            .unwrap();
+        PasskeyRepository::link_existing_user(&pool, user.id(), account_id)
+            .await
+            .unwrap();
+
+        let linked = sqlx::query_scalar::<_, i64>(
+            "SELECT account_id FROM passkey_user_accounts WHERE passkey_user_id = $1",
+        )
+        .bind(user.id())
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(linked, vec![account_id]);
+    }
+
+    #[sqlx::test]
+    async fn test_get_passkey_credentials(pool: PgPool) {
+        let user = passkey_user("dave@example.com");
+        PasskeyRepository::create_user(&pool, &user).await.unwrap();
+        for credential_id in [[1, 2, 3, 4], [5, 6, 7, 8]] {
+            PasskeyRepository::create_user_credentials(&pool, user.id(), &passkey(&credential_id))
+                .await
+                .unwrap();
+        }
+
+        let passkeys = PasskeyRepository::get_user_credentials(&pool, user.id())
+            .await
+            .unwrap();
+        assert_eq!(passkeys.len(), 2);
+
+        let credential = PasskeyRepository::get_credential_by_id(&pool, &[5, 6, 7, 8])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(credential.user_id, *user.id());
+        assert_eq!(credential.credential.cred_id().as_slice(), &[5, 6, 7, 8]);
+    }
+
+    #[sqlx::test]
+    async fn test_update_credential(pool: PgPool) {
+        let user = passkey_user("heidi@example.com");
+        PasskeyRepository::create_user(&pool, &user).await.unwrap();
+        PasskeyRepository::create_user_credentials(&pool, user.id(), &passkey(&[3, 3]))
+            .await
+            .unwrap();
+
+        let mut updated = serde_json::to_value(passkey(&[3, 3])).unwrap();
+        updated["cred"]["counter"] = json!(7);
+        let updated: Passkey = serde_json::from_value(updated).unwrap();
+        PasskeyRepository::update_credential(&pool, &[3, 3], &updated)
+            .await
+            .unwrap();
+
+        let stored = PasskeyRepository::get_credential_by_id(&pool, &[3, 3])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(to_value(&stored.credential).unwrap()["cred"]["counter"], 7);
+
+        let missing = PasskeyRepository::update_credential(&pool, &[4, 4], &updated).await;
+        assert!(matches!(missing, Err(Error::Other(message)) if message == "credential not found"));
+    }
+
+    #[sqlx::test]
+    async fn test_delete_credential(pool: PgPool) {
+        let user = passkey_user("erin@example.com");
+        let other = passkey_user("frank@example.com");
+        PasskeyRepository::create_user(&pool, &user).await.unwrap();
+        PasskeyRepository::create_user(&pool, &other).await.unwrap();
+        PasskeyRepository::create_user_credentials(&pool, user.id(), &passkey(&[1, 2, 3, 4]))
+            .await
+            .unwrap();
+
+        assert!(
+            !PasskeyRepository::delete_credential(&pool, &[1, 2, 3, 4], other.id())
+                .await
+                .unwrap()
+        );
+        assert!(
+            PasskeyRepository::delete_credential(&pool, &[1, 2, 3, 4], user.id())
+                .await
+                .unwrap()
+        );
+        assert!(
+            !PasskeyRepository::delete_credential(&pool, &[1, 2, 3, 4], user.id())
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            PasskeyRepository::count_credentials(&pool, user.id())
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_query_audit_events_by_range_and_cursor(pool: PgPool) {
+        for user_id in 1..=3 {
+            let event = AuditEventDTO {
+                user_id: Some(user_id),
+                action: AuditAction::SignUp,
+                ip_address: None,
+                user_agent: None,
+                metadata: json!({}),
+            };
+            AuditRepository::log_event(&pool, &event).await.unwrap();
+        }
+        let now = Utc::now();
+        let (from, to) = (now - TimeDelta::hours(1), now + TimeDelta::hours(1));
+
+        let first_page = AuditRepository::query_events(&pool, from, to, 0, 2)
+            .await
+            .unwrap();
+        assert_eq!(first_page.len(), 2);
+
+        let cursor = first_page[1].id();
+        let second_page = AuditRepository::query_events(&pool, from, to, cursor, 2)
+            .await
+            .unwrap();
+        assert_eq!(second_page.len(), 1);
+        assert!(second_page[0].id() > cursor);
+
+        assert_eq!(
+            AuditRepository::count_events(&pool, from, to)
+                .await
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            AuditRepository::count_events(&pool, from - TimeDelta::days(1), from)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_accept_invitation(pool: PgPool) {
+        let handler = password_handler();
+        let expires_at = Utc::now() + TimeDelta::hours(1);
+        for (token, email) in [
+            (b"first", "ivan@example.com"),
+            (b"taken", "judy@example.com"),
+        ] {
+            InvitationRepository::create(&pool, token, email, "admin", None, expires_at)
+                .await
+                .unwrap();
+        }
+        Repository::create_user_in_transaction(&pool, account("judy@example.com", &handler))
+            .await
+            .unwrap();
+        assert_eq!(
+            InvitationRepository::list_pending(&pool)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let accepted = InvitationRepository::accept(&pool, b"first", "Ivan", "password", &handler)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(accepted.role, "admin");
+        let user = Repository::get_by_mail(&pool, "ivan@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.id(), accepted.user_id);
+        assert_eq!(
+            PasswordHandler::detect_method(user.password_hash()),
+            Some(Method::Argon2id)
+        );
+        assert!(
+            InvitationRepository::accept(&pool, b"first", "Ivan", "password", &handler)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let taken =
+            InvitationRepository::accept(&pool, b"taken", "Judy", "password", &handler).await;
+        assert!(matches!(taken, Err(Error::UniqueViolation)));
+        assert_eq!(
+            InvitationRepository::list_pending(&pool)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_count_actions_since(pool: PgPool) {
+        for action in [
+            AuditAction::SignInSuccess,
+            AuditAction::PasskeySignInSuccess,
+            AuditAction::SignInFailure,
+            AuditAction::SignUp,
+        ] {
+            let event = AuditEventDTO {
+                user_id: None,
+                action,
+                ip_address: None,
+                user_agent: None,
+                metadata: json!({}),
+            };
+            AuditRepository::log_event(&pool, &event).await.unwrap();
+        }
+        let since = Utc::now() - TimeDelta::hours(1);
+
+        let sign_ins = [
+            AuditAction::SignInSuccess,
+            AuditAction::PasskeySignInSuccess,
+        ];
+        assert_eq!(
+            AuditRepository::count_actions_since(&pool, &sign_ins, since)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            AuditRepository::count_actions_since(&pool, &sign_ins, Utc::now())
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_no_orphaned_query_files() {
+        let source = include_str!("repository.rs");
+        for file in QUERY_FILES {
+            assert!(
+                source.contains(&format!("\"{file}\"")),
+                "{file} is not used by any query"
+            );
+        }
+    }
 }
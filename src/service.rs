@@ -1,456 +1,2385 @@
-use std::{collections::HashMap, fmt::Display, sync::Mutex};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use actix_web::{
-    HttpResponse, Responder, ResponseError, get, post,
-    web::{self, Data},
+    HttpMessage, HttpRequest, HttpResponse, Responder, delete,
+    error::{InternalError, JsonPayloadError},
+    get,
+    http::{
+        StatusCode,
+        header::{AUTHORIZATION, CACHE_CONTROL, RETRY_AFTER, USER_AGENT},
+    },
+    middleware::DefaultHeaders,
+    patch, post, web,
 };
+use chrono::{DateTime, TimeDelta, Utc};
+use rand::distr::{Alphanumeric, SampleString};
 use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
-use webauthn_rs::{
-    Webauthn,
-    prelude::{
-        CreationChallengeResponse, DiscoverableAuthentication, DiscoverableKey,
-        PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
-        RegisterPublicKeyCredential, RequestChallengeResponse, Uuid,
-    },
-};
+use tokio::sync::RwLock;
+use utoipa::{OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+use webauthn_rs::prelude::{DiscoverableAuthentication, Uuid, WebauthnError};
 
 use crate::{
-    crypto::{Method, PasswordHandler},
-    repository::{PasskeyRepository, PasskeyUser, Repository, UserDTO},
+    config::{AccountPolicy, AppConfiguration},
+    error::Error,
+    middleware::{
+        circuit_breaker::CircuitBreakerMiddleware, internal_secret::InternalSecretMiddleware,
+        maintenance::set_maintenance_mode, passkey_rate_limit::PasskeyRateLimiterMiddleware,
+        real_ip::RealIp,
+    },
+    repository::{
+        AdminUserDetail, AuditAction, AuditEventDTO, AuditRepository, IdempotencyRepository,
+        Invitation, InvitationRepository, PasskeyRepository, Repository, UserStatus,
+    },
+    store::{Challenge, ChallengeStore, Insertion},
 };
 
 use log::{Level, log};
+use prometheus::TextEncoder;
 
-#[derive(Debug, Serialize)]
-struct ServiceError {
+mod auth;
+pub mod passkey;
+pub mod password;
+
+use passkey::{PendingPasskeyAuthentication, PendingPasskeyRegistration};
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct ServiceError {
     kind: ErrorKind,
     message: String,
 }
 
+/// A validation error that names the offending request field.
+#[derive(Debug, Serialize, ToSchema)]
+struct FieldError {
+    #[serde(flatten)]
+    error: ServiceError,
+    field: &'static str,
+}
+
+impl Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.kind, self.message)
+    }
+}
+
+impl From<Error> for ServiceError {
+    fn from(value: Error) -> Self {
+        if value.is_unique_violation() {
+            return Self {
+                kind: ErrorKind::AlreadyExists,
+                message: "Resource already exists".into(),
+            };
+        }
+
+        log!(Level::Error, "{value}");
+        Self {
+            kind: ErrorKind::InternalServerError,
+            message: "An unexpected error occurred".into(),
+        }
+    }
+}
+
 impl ServiceError {
-    fn internal_server_error() -> HttpResponse {
-        HttpResponse::InternalServerError().json(Self {
+    fn status_code(&self) -> StatusCode {
+        match self.kind {
+            ErrorKind::AccountSuspended => StatusCode::LOCKED,
+            ErrorKind::AlreadyExists
+            | ErrorKind::ConfirmationInvalid
+            | ErrorKind::DuplicateRequest
+            | ErrorKind::LastCredential => StatusCode::CONFLICT,
+            ErrorKind::AuthenticationFailure => StatusCode::UNAUTHORIZED,
+            ErrorKind::ChallengeExpired => StatusCode::REQUEST_TIMEOUT,
+            ErrorKind::CredentialCloned | ErrorKind::PasskeyOnly => StatusCode::UNAUTHORIZED,
+            ErrorKind::DoesNotExist => StatusCode::NOT_FOUND,
+            ErrorKind::DomainNotAllowed | ErrorKind::Forbidden => StatusCode::FORBIDDEN,
+            ErrorKind::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorKind::LimitExceeded => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            ErrorKind::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorKind::Maintenance
+            | ErrorKind::RegistrationDisabled
+            | ErrorKind::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::ResidentKeyMissing => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorKind::ValidationError => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(self)
+    }
+
+    pub(crate) fn internal_server_error() -> HttpResponse {
+        Self {
             kind: ErrorKind::InternalServerError,
-            message: format!("An unexpected error occurred"),
-        })
+            message: "An unexpected error occurred".into(),
+        }
+        .into_response()
+    }
+
+    fn account_suspended() -> HttpResponse {
+        Self {
+            kind: ErrorKind::AccountSuspended,
+            message: "Account has been suspended".into(),
+        }
+        .into_response()
+    }
+
+    fn registration_disabled() -> HttpResponse {
+        Self {
+            kind: ErrorKind::RegistrationDisabled,
+            message: "New registrations are temporarily closed".into(),
+        }
+        .into_response()
+    }
+
+    fn domain_not_allowed() -> HttpResponse {
+        Self {
+            kind: ErrorKind::DomainNotAllowed,
+            message: "Registration is restricted to approved domains".into(),
+        }
+        .into_response()
+    }
+
+    fn invalid_field(field: &'static str, message: impl Into<String>) -> HttpResponse {
+        let error = Self {
+            kind: ErrorKind::ValidationError,
+            message: message.into(),
+        };
+        HttpResponse::build(error.status_code()).json(FieldError { error, field })
+    }
+
+    fn weak_password(violations: Vec<String>) -> HttpResponse {
+        Self::invalid_field(
+            "password",
+            format!("Password is too weak: {}", violations.join(", ")),
+        )
+    }
+
+    fn limit_exceeded() -> HttpResponse {
+        Self {
+            kind: ErrorKind::LimitExceeded,
+            message: "Too many pending passkey challenges, try again later".into(),
+        }
+        .into_response()
+    }
+
+    fn passkey_authentication_failure(err: &WebauthnError) -> HttpResponse {
+        match err {
+            WebauthnError::CredentialPossibleCompromise => Self {
+                kind: ErrorKind::CredentialCloned,
+                message: "Passkey counter went backwards, the credential may be cloned".into(),
+            },
+            _ => Self {
+                kind: ErrorKind::AuthenticationFailure,
+                message: "Could not authenticate passkey".into(),
+            },
+        }
+        .into_response()
+    }
+
+    fn challenge_expired() -> HttpResponse {
+        Self {
+            kind: ErrorKind::ChallengeExpired,
+            message: "Passkey challenge has expired".into(),
+        }
+        .into_response()
+    }
+
+    pub(crate) fn service_unavailable() -> HttpResponse {
+        Self {
+            kind: ErrorKind::ServiceUnavailable,
+            message: "Service is temporarily unavailable, try again later".into(),
+        }
+        .into_response()
+    }
+
+    pub(crate) fn rate_limited(retry_after: Duration) -> HttpResponse {
+        let error = Self {
+            kind: ErrorKind::LimitExceeded,
+            message: "Too many requests, try again later".into(),
+        };
+        HttpResponse::build(error.status_code())
+            .insert_header((RETRY_AFTER, retry_after.as_secs_f64().ceil() as u64))
+            .json(error)
+    }
+
+    pub(crate) fn maintenance(message: String) -> HttpResponse {
+        Self {
+            kind: ErrorKind::Maintenance,
+            message,
+        }
+        .into_response()
+    }
+
+    pub(crate) fn duplicate_request() -> HttpResponse {
+        Self {
+            kind: ErrorKind::DuplicateRequest,
+            message: "A request with this idempotency key is still in progress".into(),
+        }
+        .into_response()
+    }
+
+    pub(crate) fn invalid_idempotency_key() -> HttpResponse {
+        Self {
+            kind: ErrorKind::ValidationError,
+            message: "Idempotency-Key must be a UUID".into(),
+        }
+        .into_response()
     }
 }
 
-#[derive(Debug, Serialize)]
-enum ErrorKind {
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub(crate) enum ErrorKind {
+    AccountSuspended,
     AlreadyExists,
     AuthenticationFailure,
+    ChallengeExpired,
+    ConfirmationInvalid,
+    CredentialCloned,
     DoesNotExist,
+    DomainNotAllowed,
+    DuplicateRequest,
+    Forbidden,
     InternalServerError,
+    LastCredential,
+    LimitExceeded,
+    Maintenance,
+    NotImplemented,
+    PasskeyOnly,
+    PayloadTooLarge,
+    RegistrationDisabled,
+    ResidentKeyMissing,
+    ServiceUnavailable,
+    ValidationError,
 }
 
-#[derive(Debug, Deserialize)]
-struct SignUpRequest {
-    name: String,
-    password: String,
-    mail: String,
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
 }
 
-#[post("/sign-up")]
-async fn sign_up(
-    user: web::Json<SignUpRequest>,
-    pool: web::ThinData<PgPool>,
-    handler: web::Data<PasswordHandler>,
-) -> impl Responder {
-    let result = Repository::create_user(
-        &pool,
-        UserDTO::new(&user.mail, &user.name, &user.password, &handler),
-    )
-    .await;
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(health)
+        .service(ready)
+        .service(
+            SwaggerUi::new("/api/swagger-ui/{_:.*}").url("/api/openapi.json", ApiDoc::openapi()),
+        )
+        .service(
+            web::scope("/api/v1")
+                .wrap(CircuitBreakerMiddleware)
+                .service(version)
+                .configure(password::configure)
+                .service(
+                    web::scope("/auth")
+                        .wrap(no_store())
+                        .configure(auth::configure),
+                )
+                .service(
+                    web::scope("/passkeys")
+                        .wrap(no_store())
+                        .wrap(PasskeyRateLimiterMiddleware)
+                        .configure(passkey::configure),
+                ),
+        );
+}
 
-    match result {
-        Ok(_) => HttpResponse::Created().finish(),
-        Err(err) => {
-            if err.is_unique_violation() {
-                HttpResponse::Conflict().json(ServiceError {
-                    kind: ErrorKind::AlreadyExists,
-                    message: format!("User already exists"),
-                })
-            } else {
-                ServiceError::internal_server_error()
+/// OpenAPI description of the public API, served at `/api/openapi.json`.
+#[derive(OpenApi)]
+#[openapi(
+    paths(health, ready),
+    nest((path = "/api/v1", api = ApiV1)),
+)]
+pub struct ApiDoc;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        version,
+        password::sign_up,
+        password::sign_in,
+    ),
+    nest(
+        (path = "/auth", api = auth::AuthApi, tags = ["auth"]),
+        (path = "/passkeys", api = passkey::PasskeyApi, tags = ["passkeys"]),
+    ),
+)]
+struct ApiV1;
+
+fn no_store() -> DefaultHeaders {
+    DefaultHeaders::new().add((CACHE_CONTROL, "no-store"))
+}
+
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    match err {
+        JsonPayloadError::OverflowKnownLength { limit, .. }
+        | JsonPayloadError::Overflow { limit } => {
+            let response = ServiceError {
+                kind: ErrorKind::PayloadTooLarge,
+                message: format!("Request body exceeds the limit of {limit} bytes"),
             }
+            .into_response();
+            InternalError::from_response(err, response).into()
         }
+        err => err.into(),
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct SignInRequest {
-    mail: String,
-    password: String,
+fn log_audit_event(
+    pool: &PgPool,
+    request: &HttpRequest,
+    action: AuditAction,
+    user_id: Option<i64>,
+    metadata: Value,
+) {
+    let pool = pool.clone();
+    let event = AuditEventDTO {
+        user_id,
+        action,
+        ip_address: request
+            .extensions()
+            .get::<RealIp>()
+            .map(|real_ip| real_ip.0),
+        user_agent: request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from),
+        metadata,
+    };
+
+    tokio::spawn(async move {
+        if let Err(err) = AuditRepository::log_event(&pool, &event).await {
+            log!(Level::Error, "Audit log: {err}");
+        }
+    });
+}
+
+/// Prometheus metrics in the text exposition format. Only served on the
+/// internal port.
+#[get("/metrics")]
+pub async fn metrics() -> impl Responder {
+    match TextEncoder::new().encode_to_string(&prometheus::gather()) {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(prometheus::TEXT_FORMAT)
+            .body(body),
+        Err(err) => {
+            log!(Level::Error, "Metrics encoding: {err}");
+            ServiceError::internal_server_error()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PurgedChallenges {
+    purged: usize,
+    purged_idempotency_keys: u64,
+    purged_deleted_users: u64,
 }
 
-#[post("/sign-in")]
-async fn sign_in(
-    user: web::Json<SignInRequest>,
+#[post("/internal/purge-expired-challenges")]
+async fn purge_expired_challenges(
+    request: HttpRequest,
     pool: web::ThinData<PgPool>,
-    handler: web::Data<PasswordHandler>,
+    app_config: web::ThinData<Arc<AppConfiguration>>,
+    registration_store: web::Data<ChallengeStore<PendingPasskeyRegistration>>,
+    authentication_store: web::Data<ChallengeStore<PendingPasskeyAuthentication>>,
+    discoverable_store: web::Data<ChallengeStore<DiscoverableAuthentication>>,
 ) -> impl Responder {
-    let result = Repository::get_by_mail(&pool, &user.mail).await;
+    if let Err(response) = authorize_internal(&request, &app_config.internal_secret) {
+        return response;
+    }
+
+    let purged = [
+        registration_store.purge_expired(),
+        authentication_store.purge_expired(),
+        discoverable_store.purge_expired(),
+    ]
+    .into_iter()
+    .sum::<usize>();
+
+    let deleted_before = Utc::now() - TimeDelta::days(app_config.gdpr_retention_days);
+    let result = tokio::try_join!(
+        IdempotencyRepository::purge_expired(&pool),
+        Repository::purge_deleted_users(&pool, deleted_before),
+    );
 
     match result {
-        Ok(Some(user_details)) => {
-            if handler.is_hash_of(
-                &user.password,
-                user_details.password_hash(),
-                Method::SaltPepper,
-            ) {
-                HttpResponse::Ok().finish()
-            } else {
-                HttpResponse::Unauthorized().json(ServiceError {
-                    kind: ErrorKind::AuthenticationFailure,
-                    message: format!("Failed to authenticate"),
-                })
-            }
+        Ok((purged_idempotency_keys, purged_deleted_users)) => {
+            log!(
+                Level::Info,
+                "Purged {purged} expired challenges, {purged_idempotency_keys} idempotency keys and {purged_deleted_users} deleted users"
+            );
+            HttpResponse::Ok().json(PurgedChallenges {
+                purged,
+                purged_idempotency_keys,
+                purged_deleted_users,
+            })
         }
-        Ok(None) => HttpResponse::NotFound().json(ServiceError {
-            kind: ErrorKind::DoesNotExist,
-            message: format!("User does not exist"),
-        }),
         Err(_) => ServiceError::internal_server_error(),
     }
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+struct VersionInfo {
+    version: &'static str,
+    build_commit: Option<&'static str>,
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Build information", body = VersionInfo),
+    )
+)]
+#[get("/version")]
+async fn version() -> impl Responder {
+    HttpResponse::Ok().json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        build_commit: option_env!("GIT_HASH"),
+    })
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Service is running"),
+    )
+)]
+#[get("/health")]
+async fn health() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Database is reachable"),
+        (status = 503, description = "Database is unreachable"),
+    )
+)]
+#[get("/ready")]
+async fn ready(pool: web::ThinData<PgPool>) -> impl Responder {
+    match Repository::ping(&pool).await {
+        Ok(()) => HttpResponse::Ok().json(json!({ "status": "ready" })),
+        Err(err) => {
+            log!(Level::Warn, "Readiness check failed: {err}");
+            HttpResponse::ServiceUnavailable().json(json!({ "status": "unavailable" }))
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct Pagination {
     page: Option<i64>,
     page_size: Option<i64>,
 }
 
+#[derive(Debug, Serialize)]
+struct PaginatedResponse<T> {
+    items: Vec<T>,
+    total: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<i64>,
+    page_size: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CredentialsQuery {
+    /// Prefix of the mail or name to match, case-insensitively.
+    q: Option<String>,
+    cursor: Option<i64>,
+    page_size: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct FilteredResponse<T> {
+    items: Vec<T>,
+    filter_applied: Option<String>,
+    next_cursor: Option<i64>,
+}
+
 #[get("/user-credentials")]
 async fn user_credentials(
-    pagination: web::Query<Pagination>,
+    query: web::Query<CredentialsQuery>,
     pool: web::ThinData<PgPool>,
+    app_config: web::ThinData<Arc<AppConfiguration>>,
 ) -> impl Responder {
-    let result = Repository::get_credentials(
+    let CredentialsQuery {
+        q,
+        cursor,
+        page_size,
+    } = query.into_inner();
+    let filter = q.filter(|q| !q.is_empty());
+    let page_size = page_size.unwrap_or(app_config.default_page_size);
+
+    match Repository::get_credentials_filtered(
         &pool,
-        pagination.page.unwrap_or(0),
-        pagination.page_size.unwrap_or(10),
+        filter.as_deref(),
+        page_size,
+        cursor.unwrap_or(0),
     )
-    .await;
-
-    match result {
-        Ok(users) => HttpResponse::Ok().json(users),
+    .await
+    {
+        Ok(items) => {
+            let next_cursor = match items.last() {
+                Some(last) if items.len() as i64 == page_size => Some(last.id()),
+                _ => None,
+            };
+            HttpResponse::Ok().json(FilteredResponse {
+                items,
+                filter_applied: filter,
+                next_cursor,
+            })
+        }
         Err(_) => ServiceError::internal_server_error(),
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct StartPasskeyRegistration {
-    mail: String,
-    name: String,
-}
-
-#[derive(Debug, Serialize)]
-struct PasskeyCreationChallenge {
-    user_id: Uuid,
-    creation_challenge_response: CreationChallengeResponse,
+/// Operator endpoints. Only served to callers presenting the internal
+/// secret.
+pub fn configure_admin(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .wrap(InternalSecretMiddleware)
+            .service(maintenance_mode)
+            .service(clear_challenge_store)
+            .service(delete_user)
+            .service(update_user_role)
+            .service(invite_user)
+            .service(pending_invitations)
+            .service(lock_user)
+            .service(unlock_user)
+            .service(audit_log)
+            .service(user_credentials)
+            .service(search_users)
+            .service(user_detail)
+            .service(effective_config)
+            .service(passkey_stats)
+            .service(admin_stats)
+            .service(integrity_check),
+    );
 }
 
-#[post("/passkey/start-registration")]
-async fn start_passkey_registration(
-    registration: web::Json<StartPasskeyRegistration>,
-    pool: web::ThinData<PgPool>,
-    webauthn: web::Data<Webauthn>,
-    registration_store: web::Data<Mutex<HashMap<Uuid, PasskeyRegistration>>>,
-) -> impl Responder {
-    let (user_id, credentials) =
-        match PasskeyRepository::get_user_by_mail(&pool, &registration.mail).await {
-            Ok(Some(user)) => {
-                let credentials =
-                    match PasskeyRepository::get_user_credential_ids(&pool, user.id()).await {
-                        Ok(credentials) => credentials,
-                        Err(_) => return ServiceError::internal_server_error(),
-                    };
-                (user.id().clone(), Some(credentials))
-            }
-            Ok(None) => (Uuid::new_v4(), None),
-            Err(_) => return ServiceError::internal_server_error(),
-        };
+/// Checks that the request carries `internal_secret` as bearer token. Every
+/// request is rejected while no secret is configured.
+pub(crate) fn authorize_internal(
+    request: &HttpRequest,
+    internal_secret: &str,
+) -> Result<(), HttpResponse> {
+    if internal_secret.is_empty() {
+        return Err(ServiceError {
+            kind: ErrorKind::NotImplemented,
+            message: "Internal endpoints are disabled".into(),
+        }
+        .into_response());
+    }
 
-    if credentials.is_none() {
-        match PasskeyRepository::create_user(
-            &pool,
-            &PasskeyUser {
-                id: user_id,
-                mail: registration.mail.clone(),
-                name: registration.name.clone(),
-            },
-        )
-        .await
-        {
-            Ok(_) => {}
-            Err(err) => {
-                log!(Level::Error, "Creation: {err}");
-                return ServiceError::internal_server_error();
-            }
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        Some(token) if secrets_match(token, internal_secret) => Ok(()),
+        Some(_) => Err(ServiceError {
+            kind: ErrorKind::Forbidden,
+            message: "Invalid internal secret".into(),
         }
+        .into_response()),
+        None => Err(ServiceError {
+            kind: ErrorKind::AuthenticationFailure,
+            message: "Missing internal secret".into(),
+        }
+        .into_response()),
     }
+}
 
-    let (creation_challenge_response, passkey_registration) = match webauthn
-        .start_passkey_registration(user_id, &registration.mail, &registration.name, credentials)
-    {
-        Ok(registration_data) => registration_data,
-        Err(_) => return ServiceError::internal_server_error(),
-    };
+/// Compares the SHA-256 digests of both secrets in constant time, so neither
+/// the contents nor the length of the expected secret leak through timing.
+fn secrets_match(left: &str, right: &str) -> bool {
+    Sha256::digest(left.as_bytes())
+        .iter()
+        .zip(Sha256::digest(right.as_bytes()).iter())
+        .fold(0, |diff, (left, right)| diff | (left ^ right))
+        == 0
+}
+
+const DEFAULT_MAINTENANCE_MESSAGE: &str = "Service is under maintenance, try again later";
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct MaintenanceMode {
+    enabled: bool,
+    message: Option<String>,
+}
+
+/// Switches maintenance mode for all workers of the public server. While
+/// enabled every route except health and readiness answers `503`.
+#[post("/maintenance")]
+async fn maintenance_mode(
+    request: HttpRequest,
+    mode: web::Json<MaintenanceMode>,
+    pool: web::ThinData<PgPool>,
+) -> impl Responder {
+    let MaintenanceMode { enabled, message } = mode.into_inner();
+    let message = message
+        .filter(|message| !message.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_MAINTENANCE_MESSAGE.into());
+    set_maintenance_mode(enabled, message.clone());
     log!(
-        Level::Info,
-        "Issued Challenge: {:?}",
-        creation_challenge_response,
+        Level::Warn,
+        "Maintenance mode {}",
+        if enabled { "enabled" } else { "disabled" }
     );
 
-    match registration_store.lock() {
-        Ok(mut store) => {
-            store.insert(user_id, passkey_registration);
-            HttpResponse::Ok().json(PasskeyCreationChallenge {
-                user_id,
-                creation_challenge_response,
-            })
-        }
-        Err(_) => ServiceError::internal_server_error(),
-    }
+    log_audit_event(
+        &pool,
+        &request,
+        AuditAction::MaintenanceChanged,
+        None,
+        json!({ "enabled": enabled, "message": message }),
+    );
+    HttpResponse::Ok().json(MaintenanceMode {
+        enabled,
+        message: Some(message),
+    })
+}
+
+#[get("/config")]
+async fn effective_config(app_config: web::ThinData<Arc<AppConfiguration>>) -> impl Responder {
+    HttpResponse::Ok().json(app_config.0.as_ref())
 }
 
 #[derive(Debug, Deserialize)]
-struct FinishPasskeyRegistration {
-    user_id: Uuid,
-    register_public_key_credential: RegisterPublicKeyCredential,
+struct UserSearch {
+    q: String,
 }
 
-#[post("/passkey/finish-registration")]
-async fn finish_passkey_registration(
-    registration: web::Json<FinishPasskeyRegistration>,
+#[get("/users")]
+async fn search_users(
+    search: web::Query<UserSearch>,
+    pagination: web::Query<Pagination>,
     pool: web::ThinData<PgPool>,
-    webauthn: web::Data<Webauthn>,
-    registration_store: web::Data<Mutex<HashMap<Uuid, PasskeyRegistration>>>,
+    app_config: web::ThinData<Arc<AppConfiguration>>,
 ) -> impl Responder {
-    let passkey_registration = match registration_store
-        .lock()
-        .map(|mut store| store.remove(&registration.user_id))
-    {
-        Ok(Some(passkey_registration)) => passkey_registration,
-        Ok(None) => {
-            return HttpResponse::NotFound().json(ServiceError {
-                kind: ErrorKind::DoesNotExist,
-                message: format!("Passkey registration does not exist"),
-            });
-        }
-        Err(err) => {
-            log!(Level::Error, "Mutex eroro?????: {err}");
-            return ServiceError::internal_server_error();
-        }
-    };
+    let page = pagination.page.unwrap_or(0);
+    let page_size = pagination.page_size.unwrap_or(app_config.default_page_size);
 
-    let passkey = match webauthn.finish_passkey_registration(
-        &registration.register_public_key_credential,
-        &passkey_registration,
-    ) {
-        Ok(passkey) => passkey,
-        Err(err) => {
-            log!(Level::Error, "{err}");
-            return HttpResponse::BadRequest().json(ServiceError {
-                kind: ErrorKind::AuthenticationFailure,
-                message: format!("Failed to authenticate passkey"),
-            });
-        }
-    };
+    let result = tokio::try_join!(
+        Repository::search_users(&pool, &search.q, page, page_size),
+        Repository::count_search_users(&pool, &search.q),
+    );
 
-    match PasskeyRepository::create_user_credentials(&pool, &registration.user_id, &passkey).await {
-        Ok(_) => HttpResponse::Created().finish(),
-        Err(err) => {
-            if err.is_unique_violation() {
-                HttpResponse::Conflict().json(ServiceError {
-                    kind: ErrorKind::AlreadyExists,
-                    message: format!("Credential id already exists"),
-                })
-            } else {
-                log!(Level::Error, "Credential creation: {err}");
-                ServiceError::internal_server_error()
-            }
-        }
+    match result {
+        Ok((items, total)) => HttpResponse::Ok().json(PaginatedResponse {
+            items,
+            total,
+            page: Some(page),
+            page_size,
+            next_cursor: None,
+        }),
+        Err(_) => ServiceError::internal_server_error(),
     }
 }
 
 #[derive(Debug, Deserialize)]
-struct StartPasskeyAuthentication {
-    mail: String,
-}
-
-#[derive(Debug, Serialize)]
-struct PasskeyRequestChallenge {
-    user_id: Uuid,
-    request_challenge_response: RequestChallengeResponse,
+struct AuditLogQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    cursor: Option<i64>,
+    page_size: Option<i64>,
 }
 
-#[post("/passkey/start-authentication")]
-async fn start_passkey_authentication(
-    authentication: web::Json<StartPasskeyAuthentication>,
+#[get("/audit-log")]
+async fn audit_log(
+    query: web::Query<AuditLogQuery>,
     pool: web::ThinData<PgPool>,
-    webauthn: web::Data<Webauthn>,
-    authentication_store: web::Data<Mutex<HashMap<Uuid, PasskeyAuthentication>>>,
+    app_config: web::ThinData<Arc<AppConfiguration>>,
 ) -> impl Responder {
-    let user_id = match PasskeyRepository::get_user_by_mail(&pool, &authentication.mail).await {
-        Ok(Some(user)) => user.id().clone(),
-        Ok(None) => {
-            return HttpResponse::NotFound().json(ServiceError {
-                kind: ErrorKind::DoesNotExist,
-                message: format!("User does not exist"),
-            });
+    let max_range = TimeDelta::days(app_config.audit_log_max_range_days);
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - max_range);
+    if from > to {
+        return ServiceError {
+            kind: ErrorKind::ValidationError,
+            message: "`from` must not be after `to`".into(),
         }
-        Err(_) => return ServiceError::internal_server_error(),
-    };
+        .into_response();
+    }
+    if to - from > max_range {
+        return ServiceError {
+            kind: ErrorKind::ValidationError,
+            message: format!(
+                "Time range must not exceed {} days",
+                app_config.audit_log_max_range_days
+            ),
+        }
+        .into_response();
+    }
 
-    let passkeys = match PasskeyRepository::get_user_credentials(&pool, &user_id).await {
-        Ok(passkeys) => passkeys,
-        Err(_) => return ServiceError::internal_server_error(),
-    };
+    let cursor = query.cursor.unwrap_or(0);
+    let page_size = query.page_size.unwrap_or(app_config.default_page_size);
 
-    let (request_challenge_response, passkey_authentication) =
-        match webauthn.start_passkey_authentication(passkeys.as_slice()) {
-            Ok((request_challenge_response, passkey_authentication)) => {
-                (request_challenge_response, passkey_authentication)
-            }
-            Err(_) => return ServiceError::internal_server_error(),
-        };
+    let result = tokio::try_join!(
+        AuditRepository::query_events(&pool, from, to, cursor, page_size),
+        AuditRepository::count_events(&pool, from, to),
+    );
 
-    match authentication_store.lock() {
-        Ok(mut store) => {
-            store.insert(user_id, passkey_authentication);
-            HttpResponse::Ok().json(PasskeyRequestChallenge {
-                user_id: user_id,
-                request_challenge_response,
+    match result {
+        Ok((items, total)) => {
+            let next_cursor = match items.last() {
+                Some(last) if items.len() as i64 == page_size => Some(last.id()),
+                _ => None,
+            };
+            HttpResponse::Ok().json(PaginatedResponse {
+                items,
+                total,
+                page: None,
+                page_size,
+                next_cursor,
             })
         }
         Err(_) => ServiceError::internal_server_error(),
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct FinishPasskeyAuthentication {
-    user_id: Uuid,
-    public_key_credential: PublicKeyCredential,
+#[derive(Serialize)]
+struct UserDetail {
+    #[serde(flatten)]
+    user: AdminUserDetail,
+    status: UserStatus,
 }
 
-#[post("/passkey/finish-authentication")]
-async fn finish_passkey_authentication(
-    authentication: web::Json<FinishPasskeyAuthentication>,
-    webauthn: web::Data<Webauthn>,
-    authentication_store: web::Data<Mutex<HashMap<Uuid, PasskeyAuthentication>>>,
-) -> impl Responder {
-    let passkey_authentication = match authentication_store
-        .lock()
-        .map(|mut store| store.remove(&authentication.user_id))
-    {
-        Ok(Some(passkey_authentication)) => passkey_authentication,
-        Ok(None) => {
-            return HttpResponse::NotFound().json(ServiceError {
-                kind: ErrorKind::DoesNotExist,
-                message: format!("Passkey authentication does not exist"),
-            });
+impl From<AdminUserDetail> for UserDetail {
+    fn from(user: AdminUserDetail) -> Self {
+        Self {
+            status: user.status(),
+            user,
         }
-        Err(_) => return ServiceError::internal_server_error(),
-    };
+    }
+}
 
-    let _result = match webauthn.finish_passkey_authentication(
-        &authentication.public_key_credential,
-        &passkey_authentication,
-    ) {
-        Ok(result) => result,
-        Err(_) => {
-            return HttpResponse::Unauthorized().json(ServiceError {
-                kind: ErrorKind::AuthenticationFailure,
-                message: format!("Could not authenticate passkey"),
-            });
+#[get("/users/{user_id}")]
+async fn user_detail(user_id: web::Path<i64>, pool: web::ThinData<PgPool>) -> impl Responder {
+    match Repository::get_user_by_id_admin(&pool, user_id.into_inner()).await {
+        Ok(Some(user)) => HttpResponse::Ok().json(UserDetail::from(user)),
+        Ok(None) => ServiceError {
+            kind: ErrorKind::DoesNotExist,
+            message: "User does not exist".into(),
         }
-    };
-
-    HttpResponse::Ok().finish()
+        .into_response(),
+        Err(_) => ServiceError::internal_server_error(),
+    }
 }
 
-#[post("/passkey/start-discoverable-authentication")]
-async fn start_discoverable_authentication(
-    webauthn: web::Data<Webauthn>,
-    discoverable_store: web::Data<Mutex<HashMap<Uuid, DiscoverableAuthentication>>>,
-) -> impl Responder {
-    let (request_challenge_response, discoverable_authentication) =
-        match webauthn.start_discoverable_authentication() {
-            Ok(result) => result,
-            Err(_) => {
-                return ServiceError::internal_server_error();
-            }
-        };
+#[derive(Debug, Serialize)]
+struct AccountLockState {
+    user_id: i64,
+    locked: bool,
+}
 
-    match discoverable_store.lock() {
-        Ok(mut store) => {
-            let uuid = Uuid::new_v4();
-            store.insert(uuid, discoverable_authentication);
-            HttpResponse::Ok().json(PasskeyRequestChallenge {
-                user_id: uuid,
-                request_challenge_response,
-            })
+async fn set_account_lock(
+    request: &HttpRequest,
+    pool: &PgPool,
+    user_id: i64,
+    locked: bool,
+) -> HttpResponse {
+    match Repository::set_locked(pool, user_id, locked).await {
+        Ok(Some(locked)) => {
+            let action = if locked {
+                AuditAction::AccountLocked
+            } else {
+                AuditAction::AccountUnlocked
+            };
+            log_audit_event(pool, request, action, Some(user_id), json!({}));
+            HttpResponse::Ok().json(AccountLockState { user_id, locked })
         }
+        Ok(None) => ServiceError {
+            kind: ErrorKind::DoesNotExist,
+            message: "User does not exist".into(),
+        }
+        .into_response(),
         Err(_) => ServiceError::internal_server_error(),
     }
 }
 
-#[post("/passkey/finish-discoverable-authentication")]
-async fn finish_discoverable_authentication(
-    authentication: web::Json<FinishPasskeyAuthentication>,
+#[post("/users/{user_id}/lock")]
+async fn lock_user(
+    request: HttpRequest,
+    user_id: web::Path<i64>,
     pool: web::ThinData<PgPool>,
-    webauthn: web::Data<Webauthn>,
-    discoverable_store: web::Data<Mutex<HashMap<Uuid, DiscoverableAuthentication>>>,
 ) -> impl Responder {
-    let (user_id, passkey_id) = match webauthn
-        .identify_discoverable_authentication(&authentication.public_key_credential)
-    {
-        Ok(result) => result,
-        Err(_) => {
-            return ServiceError::internal_server_error();
-        }
-    };
+    set_account_lock(&request, &pool, user_id.into_inner(), true).await
+}
 
-    let passkey = match PasskeyRepository::get_user_credential(&pool, &user_id, passkey_id).await {
-        Ok(Some(passkey)) => passkey,
-        Ok(None) => {
-            return HttpResponse::NotFound().json(ServiceError {
-                kind: ErrorKind::DoesNotExist,
-                message: format!("Passkey does not exist"),
-            });
-        }
-        Err(_) => return ServiceError::internal_server_error(),
-    };
-    let discoverable_authentication = match discoverable_store
-        .lock()
-        .map(|mut store| store.remove(&authentication.user_id))
-    {
-        Ok(Some(discoverable_authentication)) => discoverable_authentication,
-        Ok(None) => {
-            return HttpResponse::NotFound().json(ServiceError {
-                kind: ErrorKind::DoesNotExist,
-                message: format!("Passkey authentication does not exist"),
-            });
-        }
-        Err(_) => return ServiceError::internal_server_error(),
-    };
+#[post("/users/{user_id}/unlock")]
+async fn unlock_user(
+    request: HttpRequest,
+    user_id: web::Path<i64>,
+    pool: web::ThinData<PgPool>,
+) -> impl Responder {
+    set_account_lock(&request, &pool, user_id.into_inner(), false).await
+}
 
-    let _result = match webauthn.finish_discoverable_authentication(
-        &authentication.public_key_credential,
-        discoverable_authentication,
-        &[DiscoverableKey::from(passkey)],
-    ) {
-        Ok(result) => result,
-        Err(_) => {
-            return HttpResponse::Unauthorized().json(ServiceError {
-                kind: ErrorKind::AuthenticationFailure,
-                message: format!("Could not authenticate passkey"),
-            });
+const DELETION_CONFIRMATION_TTL: Duration = Duration::from_secs(60);
+const MAX_PENDING_DELETIONS: usize = 1000;
+
+/// Confirmation tokens of pending [`delete_user`] requests, each bound to
+/// the user it was issued for.
+pub struct PendingDeletions(ChallengeStore<i64>);
+
+impl Default for PendingDeletions {
+    fn default() -> Self {
+        Self(ChallengeStore::new(
+            DELETION_CONFIRMATION_TTL,
+            MAX_PENDING_DELETIONS,
+        ))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeletionConfirmation {
+    confirm: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+struct PendingDeletion {
+    confirmation_token: Uuid,
+    expires_at: DateTime<Utc>,
+}
+
+/// Deletes an account in two steps so a mistyped id cannot remove a user
+/// right away. Without `confirm` a confirmation token is issued; repeating
+/// the request with that token within a minute deletes the account. Its
+/// personal data is erased at once, the row itself only after the GDPR
+/// retention period.
+#[delete("/users/{user_id}")]
+async fn delete_user(
+    request: HttpRequest,
+    user_id: web::Path<i64>,
+    confirmation: web::Query<DeletionConfirmation>,
+    pool: web::ThinData<PgPool>,
+    pending_deletions: web::Data<PendingDeletions>,
+) -> impl Responder {
+    let user_id = user_id.into_inner();
+    let user_does_not_exist = || {
+        ServiceError {
+            kind: ErrorKind::DoesNotExist,
+            message: "User does not exist".into(),
+        }
+        .into_response()
+    };
+
+    let Some(token) = confirmation.confirm else {
+        match Repository::get_user_by_id(&pool, user_id).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return user_does_not_exist(),
+            Err(_) => return ServiceError::internal_server_error(),
+        }
+        let token = Uuid::new_v4();
+        if let Insertion::Full = pending_deletions.0.insert(token, user_id) {
+            return ServiceError {
+                kind: ErrorKind::LimitExceeded,
+                message: "Too many pending deletions, try again later".into(),
+            }
+            .into_response();
+        }
+        log_audit_event(
+            &pool,
+            &request,
+            AuditAction::UserDeletionRequested,
+            Some(user_id),
+            json!({}),
+        );
+        return HttpResponse::Accepted().json(PendingDeletion {
+            confirmation_token: token,
+            expires_at: Utc::now()
+                + TimeDelta::from_std(DELETION_CONFIRMATION_TTL)
+                    .expect("confirmation TTL fits a TimeDelta"),
+        });
+    };
+
+    match pending_deletions
+        .0
+        .take_if(&token, |&pending_user_id| pending_user_id == user_id)
+    {
+        Challenge::Valid(_) => {}
+        Challenge::Expired | Challenge::Missing => {
+            return ServiceError {
+                kind: ErrorKind::ConfirmationInvalid,
+                message: "Confirmation token is wrong or has expired".into(),
+            }
+            .into_response();
+        }
+    }
+
+    match Repository::soft_delete_user(&pool, user_id).await {
+        Ok(true) => {
+            log_audit_event(
+                &pool,
+                &request,
+                AuditAction::UserDeleted,
+                Some(user_id),
+                json!({}),
+            );
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => user_does_not_exist(),
+        Err(_) => ServiceError::internal_server_error(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UpdateRoleRequest {
+    role: String,
+}
+
+#[patch("/users/{user_id}/role")]
+async fn update_user_role(
+    request: HttpRequest,
+    user_id: web::Path<i64>,
+    role: web::Json<UpdateRoleRequest>,
+    pool: web::ThinData<PgPool>,
+    account_policy: web::Data<AccountPolicy>,
+) -> impl Responder {
+    let user_id = user_id.into_inner();
+    if !account_policy.valid_roles.contains(&role.role) {
+        return ServiceError {
+            kind: ErrorKind::ValidationError,
+            message: format!(
+                "Role must be one of: {}",
+                account_policy.valid_roles.join(", ")
+            ),
+        }
+        .into_response();
+    }
+
+    let previous_role = match Repository::update_user_role(&pool, user_id, &role.role).await {
+        Ok(Some(previous_role)) => previous_role,
+        Ok(None) => {
+            return ServiceError {
+                kind: ErrorKind::DoesNotExist,
+                message: "User does not exist".into(),
+            }
+            .into_response();
+        }
+        Err(_) => return ServiceError::internal_server_error(),
+    };
+    log_audit_event(
+        &pool,
+        &request,
+        AuditAction::RoleChanged,
+        Some(user_id),
+        json!({ "old_role": previous_role, "new_role": role.role }),
+    );
+
+    match Repository::get_user_by_id_admin(&pool, user_id).await {
+        Ok(Some(user)) => HttpResponse::Ok().json(UserDetail::from(user)),
+        Ok(None) => ServiceError {
+            kind: ErrorKind::DoesNotExist,
+            message: "User does not exist".into(),
+        }
+        .into_response(),
+        Err(_) => ServiceError::internal_server_error(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PasskeyStats {
+    total_credentials: i64,
+    users_with_passkeys: i64,
+    users_without_passkeys: i64,
+}
+
+#[get("/passkey-stats")]
+async fn passkey_stats(pool: web::ThinData<PgPool>) -> impl Responder {
+    let result = tokio::try_join!(
+        PasskeyRepository::count_all_credentials(&pool),
+        PasskeyRepository::count_users_with_passkeys(&pool),
+        PasskeyRepository::count_users_without_credentials(&pool),
+    );
+
+    match result {
+        Ok((total_credentials, users_with_passkeys, users_without_passkeys)) => HttpResponse::Ok()
+            .json(PasskeyStats {
+                total_credentials,
+                users_with_passkeys,
+                users_without_passkeys,
+            }),
+        Err(_) => ServiceError::internal_server_error(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InviteRequest {
+    email: String,
+    role: String,
+}
+
+/// A new invitation with its token. Only the hash is stored, so this
+/// response is the only place the token can be read from.
+#[derive(Debug, Serialize)]
+struct CreatedInvitation {
+    #[serde(flatten)]
+    invitation: Invitation,
+    token: String,
+}
+
+fn hash_invitation_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+#[post("/invite")]
+async fn invite_user(
+    request: HttpRequest,
+    invite: web::Json<InviteRequest>,
+    pool: web::ThinData<PgPool>,
+    app_config: web::ThinData<Arc<AppConfiguration>>,
+    account_policy: web::Data<AccountPolicy>,
+) -> impl Responder {
+    if !account_policy.valid_roles.contains(&invite.role) {
+        return ServiceError {
+            kind: ErrorKind::ValidationError,
+            message: format!(
+                "Role must be one of: {}",
+                account_policy.valid_roles.join(", ")
+            ),
+        }
+        .into_response();
+    }
+
+    let token = Alphanumeric.sample_string(&mut rand::rng(), 43);
+    let expires_at = Utc::now() + TimeDelta::hours(app_config.invitation_expiry_hours);
+    let invitation = match InvitationRepository::create(
+        &pool,
+        &hash_invitation_token(&token),
+        &invite.email,
+        &invite.role,
+        None,
+        expires_at,
+    )
+    .await
+    {
+        Ok(invitation) => invitation,
+        Err(_) => return ServiceError::internal_server_error(),
+    };
+
+    log_audit_event(
+        &pool,
+        &request,
+        AuditAction::UserInvited,
+        None,
+        json!({ "email": invite.email, "role": invite.role }),
+    );
+    HttpResponse::Created().json(CreatedInvitation { invitation, token })
+}
+
+#[get("/invitations")]
+async fn pending_invitations(pool: web::ThinData<PgPool>) -> impl Responder {
+    match InvitationRepository::list_pending(&pool).await {
+        Ok(invitations) => HttpResponse::Ok().json(invitations),
+        Err(_) => ServiceError::internal_server_error(),
+    }
+}
+
+const ADMIN_STATS_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug, Serialize)]
+struct AdminStats {
+    total_users: i64,
+    total_passkey_users: i64,
+    total_passkeys: i64,
+    users_with_passkeys: i64,
+    orphan_credentials: i64,
+    users_registered_last_7_days: i64,
+    users_by_role: HashMap<String, i64>,
+    sign_ins_last_24h: i64,
+    failed_sign_ins_last_24h: i64,
+    cached_at: DateTime<Utc>,
+}
+
+/// Last computed [`AdminStats`], shared across workers so the aggregate
+/// queries run at most once per [`ADMIN_STATS_TTL`].
+#[derive(Default)]
+pub struct AdminStatsCache(RwLock<Option<(Instant, AdminStats)>>);
+
+impl AdminStatsCache {
+    async fn fresh(&self) -> Option<AdminStats> {
+        match self.0.read().await.as_ref() {
+            Some((refreshed_at, stats)) if refreshed_at.elapsed() < ADMIN_STATS_TTL => {
+                Some(stats.clone())
+            }
+            _ => None,
         }
+    }
+}
+
+#[get("/stats")]
+async fn admin_stats(
+    pool: web::ThinData<PgPool>,
+    cache: web::Data<AdminStatsCache>,
+) -> impl Responder {
+    if let Some(stats) = cache.fresh().await {
+        return HttpResponse::Ok().json(stats);
+    }
+
+    let mut cached = cache.0.write().await;
+    if let Some((refreshed_at, stats)) = cached.as_ref()
+        && refreshed_at.elapsed() < ADMIN_STATS_TTL
+    {
+        return HttpResponse::Ok().json(stats);
+    }
+
+    let now = Utc::now();
+    let day_ago = now - TimeDelta::hours(24);
+    let result = tokio::try_join!(
+        Repository::count_credentials(&pool),
+        PasskeyRepository::count_users(&pool),
+        PasskeyRepository::count_all_credentials(&pool),
+        PasskeyRepository::count_users_with_passkeys(&pool),
+        PasskeyRepository::count_orphan_credentials(&pool),
+        Repository::count_users_registered_between(&pool, now - TimeDelta::days(7), now),
+        Repository::count_users_by_role(&pool),
+        AuditRepository::count_actions_since(
+            &pool,
+            &[
+                AuditAction::SignInSuccess,
+                AuditAction::PasskeySignInSuccess
+            ],
+            day_ago,
+        ),
+        AuditRepository::count_actions_since(
+            &pool,
+            &[
+                AuditAction::SignInFailure,
+                AuditAction::PasskeySignInFailure
+            ],
+            day_ago,
+        ),
+    );
+
+    match result {
+        Ok((
+            total_users,
+            total_passkey_users,
+            total_passkeys,
+            users_with_passkeys,
+            orphan_credentials,
+            users_registered_last_7_days,
+            users_by_role,
+            sign_ins_last_24h,
+            failed_sign_ins_last_24h,
+        )) => {
+            let stats = AdminStats {
+                total_users,
+                total_passkey_users,
+                total_passkeys,
+                users_with_passkeys,
+                orphan_credentials,
+                users_registered_last_7_days,
+                users_by_role,
+                sign_ins_last_24h,
+                failed_sign_ins_last_24h,
+                cached_at: now,
+            };
+            *cached = Some((Instant::now(), stats.clone()));
+            HttpResponse::Ok().json(stats)
+        }
+        Err(_) => ServiceError::internal_server_error(),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct IntegrityReport {
+    orphan_credentials: i64,
+    users_with_no_credentials: i64,
+    /// Pending ceremonies past their timeout that no purge has removed yet.
+    expired_challenges: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ClearedChallenges {
+    purged_registrations: usize,
+    purged_authentications: usize,
+    purged_discoverable: usize,
+}
+
+/// Aborts every WebAuthn ceremony in progress, e.g. after challenges may
+/// have leaked. Clients have to start over.
+#[delete("/challenge-store")]
+async fn clear_challenge_store(
+    request: HttpRequest,
+    pool: web::ThinData<PgPool>,
+    registration_store: web::Data<ChallengeStore<PendingPasskeyRegistration>>,
+    authentication_store: web::Data<ChallengeStore<PendingPasskeyAuthentication>>,
+    discoverable_store: web::Data<ChallengeStore<DiscoverableAuthentication>>,
+) -> impl Responder {
+    let cleared = ClearedChallenges {
+        purged_registrations: registration_store.clear(),
+        purged_authentications: authentication_store.clear(),
+        purged_discoverable: discoverable_store.clear(),
     };
+    log!(Level::Warn, "Challenge store cleared: {cleared:?}");
+
+    log_audit_event(
+        &pool,
+        &request,
+        AuditAction::ChallengeStoreCleared,
+        None,
+        json!(cleared),
+    );
+    HttpResponse::Ok().json(cleared)
+}
+
+#[get("/integrity-check")]
+async fn integrity_check(
+    pool: web::ThinData<PgPool>,
+    registration_store: web::Data<ChallengeStore<PendingPasskeyRegistration>>,
+    authentication_store: web::Data<ChallengeStore<PendingPasskeyAuthentication>>,
+    discoverable_store: web::Data<ChallengeStore<DiscoverableAuthentication>>,
+) -> impl Responder {
+    let result = tokio::try_join!(
+        PasskeyRepository::count_orphan_credentials(&pool),
+        PasskeyRepository::count_users_without_credentials(&pool),
+    );
+
+    match result {
+        Ok((orphan_credentials, users_with_no_credentials)) => {
+            HttpResponse::Ok().json(IntegrityReport {
+                orphan_credentials,
+                users_with_no_credentials,
+                expired_challenges: registration_store.count_expired()
+                    + authentication_store.count_expired()
+                    + discoverable_store.count_expired(),
+            })
+        }
+        Err(_) => ServiceError::internal_server_error(),
+    }
+}
+
+#[cfg(test)]
+mod error_tests {
+    use super::*;
+
+    #[test]
+    #[deny(unreachable_patterns)]
+    fn test_error_kind_status_mapping() {
+        let expected = [
+            (ErrorKind::AccountSuspended, StatusCode::LOCKED),
+            (ErrorKind::AlreadyExists, StatusCode::CONFLICT),
+            (ErrorKind::AuthenticationFailure, StatusCode::UNAUTHORIZED),
+            (ErrorKind::ChallengeExpired, StatusCode::REQUEST_TIMEOUT),
+            (ErrorKind::ConfirmationInvalid, StatusCode::CONFLICT),
+            (ErrorKind::CredentialCloned, StatusCode::UNAUTHORIZED),
+            (ErrorKind::DoesNotExist, StatusCode::NOT_FOUND),
+            (ErrorKind::DomainNotAllowed, StatusCode::FORBIDDEN),
+            (ErrorKind::DuplicateRequest, StatusCode::CONFLICT),
+            (ErrorKind::Forbidden, StatusCode::FORBIDDEN),
+            (
+                ErrorKind::InternalServerError,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (ErrorKind::LastCredential, StatusCode::CONFLICT),
+            (ErrorKind::LimitExceeded, StatusCode::TOO_MANY_REQUESTS),
+            (ErrorKind::Maintenance, StatusCode::SERVICE_UNAVAILABLE),
+            (ErrorKind::NotImplemented, StatusCode::NOT_IMPLEMENTED),
+            (ErrorKind::PasskeyOnly, StatusCode::UNAUTHORIZED),
+            (ErrorKind::PayloadTooLarge, StatusCode::PAYLOAD_TOO_LARGE),
+            (
+                ErrorKind::RegistrationDisabled,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                ErrorKind::ResidentKeyMissing,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                ErrorKind::ServiceUnavailable,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (ErrorKind::ValidationError, StatusCode::BAD_REQUEST),
+        ];
+
+        for (kind, status) in expected {
+            // Adding a variant breaks this match until the table above covers it.
+            match kind {
+                ErrorKind::AccountSuspended
+                | ErrorKind::AlreadyExists
+                | ErrorKind::AuthenticationFailure
+                | ErrorKind::ChallengeExpired
+                | ErrorKind::ConfirmationInvalid
+                | ErrorKind::CredentialCloned
+                | ErrorKind::DoesNotExist
+                | ErrorKind::DomainNotAllowed
+                | ErrorKind::DuplicateRequest
+                | ErrorKind::Forbidden
+                | ErrorKind::InternalServerError
+                | ErrorKind::LastCredential
+                | ErrorKind::LimitExceeded
+                | ErrorKind::Maintenance
+                | ErrorKind::NotImplemented
+                | ErrorKind::PasskeyOnly
+                | ErrorKind::PayloadTooLarge
+                | ErrorKind::RegistrationDisabled
+                | ErrorKind::ResidentKeyMissing
+                | ErrorKind::ServiceUnavailable
+                | ErrorKind::ValidationError => {}
+            }
+
+            let json = serde_json::to_value(&kind).unwrap();
+            assert_eq!(json, Value::String(kind.to_string()));
+            assert_eq!(serde_json::from_value::<ErrorKind>(json).unwrap(), kind);
+
+            let error = ServiceError {
+                kind,
+                message: String::new(),
+            };
+            assert_eq!(error.status_code(), status, "{error}");
+        }
+    }
+
+    #[test]
+    fn test_error_json_shape() {
+        let error = ServiceError {
+            kind: ErrorKind::DoesNotExist,
+            message: "User does not exist".into(),
+        };
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({ "kind": "DoesNotExist", "message": "User does not exist" })
+        );
+
+        let error = FieldError {
+            error: ServiceError {
+                kind: ErrorKind::ValidationError,
+                message: "Invalid email format".into(),
+            },
+            field: "mail",
+        };
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({ "kind": "ValidationError", "message": "Invalid email format", "field": "mail" })
+        );
+    }
+
+    #[test]
+    fn test_maintenance_mode_round_trips() {
+        let mode: MaintenanceMode = serde_json::from_value(json!({ "enabled": true })).unwrap();
+        assert!(mode.enabled);
+        assert_eq!(mode.message, None);
+
+        let mode = MaintenanceMode {
+            enabled: false,
+            message: Some("Upgrade".into()),
+        };
+        let json = serde_json::to_string(&mode).unwrap();
+        let parsed: MaintenanceMode = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            (parsed.enabled, parsed.message),
+            (false, Some("Upgrade".into()))
+        );
+
+        let unknown = json!({ "enabled": true, "message": null, "until": "tomorrow" });
+        assert!(serde_json::from_value::<MaintenanceMode>(unknown).is_err());
+    }
+
+    #[test]
+    fn test_admin_requests_reject_unknown_fields() {
+        let role = json!({ "role": "admin", "admin": true });
+        assert!(serde_json::from_value::<UpdateRoleRequest>(role).is_err());
+        let role: UpdateRoleRequest = serde_json::from_value(json!({ "role": "admin" })).unwrap();
+        assert_eq!(role.role, "admin");
+    }
+
+    #[test]
+    fn test_secrets_match() {
+        assert!(secrets_match("Internal", "Internal"));
+        assert!(!secrets_match("Internal", "internal"));
+        assert!(!secrets_match("Internal", "Internal2"));
+        assert!(!secrets_match("", "Internal"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_http::Request;
+    use actix_web::{
+        App,
+        body::MessageBody,
+        dev::{Service, ServiceResponse},
+        http::{StatusCode, header},
+        middleware::{Compress, Condition},
+        test,
+    };
+    use flate2::read::GzDecoder;
+    use scrypt::Params as ScryptParams;
+    use std::{io::Read, sync::Arc, time::Duration};
+    use webauthn_rs::{
+        WebauthnBuilder,
+        prelude::{Base64UrlSafeData, Url, Uuid},
+    };
+
+    use super::*;
+    use crate::{
+        crypto::{
+            HashAlgorithm, Method, MockPasswordHandler, PasswordHandler, PasswordHandlerTrait,
+        },
+        middleware::compression::GzipMiddleware,
+        repository::PasskeyUser,
+        test_utils::{create_test_user, test_app_config, test_password_handler},
+    };
+
+    struct TestFixtures {
+        handler: web::Data<dyn PasswordHandlerTrait>,
+    }
+
+    impl TestFixtures {
+        fn new() -> Self {
+            Self::with_handler(Arc::new(test_password_handler()))
+        }
+
+        fn with_handler(handler: Arc<dyn PasswordHandlerTrait>) -> Self {
+            Self {
+                handler: web::Data::from(handler),
+            }
+        }
 
-    HttpResponse::Ok().finish()
+        async fn create_user(&self, pool: &PgPool, mail: &str, password: &str) -> i64 {
+            create_test_user(pool, self.handler.as_ref(), mail, password).await
+        }
+
+        async fn app(
+            &self,
+            pool: PgPool,
+        ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>
+        {
+            self.app_with_config(pool, test_app_config()).await
+        }
+
+        async fn app_with_config(
+            &self,
+            pool: PgPool,
+            app_config: AppConfiguration,
+        ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>
+        {
+            let rp_origin = Url::parse("http://localhost").unwrap();
+            let webauthn = WebauthnBuilder::new("localhost", &rp_origin)
+                .unwrap()
+                .build()
+                .unwrap();
+            let authentication_store: ChallengeStore<PendingPasskeyAuthentication> =
+                ChallengeStore::new(Duration::from_secs(60), 10);
+            let discoverable_store: ChallengeStore<DiscoverableAuthentication> =
+                ChallengeStore::new(Duration::from_secs(60), 10);
+            let registration_store: ChallengeStore<PendingPasskeyRegistration> =
+                ChallengeStore::new(Duration::from_secs(60), 10);
+            let compression_enabled = app_config.compression_enabled;
+            let compression_level = app_config.compression_level().unwrap();
+
+            test::init_service(
+                App::new()
+                    .app_data(web::ThinData(pool))
+                    .app_data(self.handler.clone())
+                    .app_data(web::Data::new(webauthn))
+                    .app_data(web::Data::new(app_config.webauthn_policy().unwrap()))
+                    .app_data(web::Data::new(authentication_store))
+                    .app_data(web::Data::new(discoverable_store))
+                    .app_data(web::Data::new(registration_store))
+                    .app_data(web::Data::new(app_config.account_policy()))
+                    .app_data(web::Data::new(app_config.password_strength()))
+                    .app_data(web::Data::new(PendingDeletions::default()))
+                    .app_data(web::Data::new(password::FailedSignIns::default()))
+                    .app_data(web::ThinData(Arc::new(app_config)))
+                    .wrap(Condition::new(
+                        compression_enabled,
+                        GzipMiddleware::new(compression_level),
+                    ))
+                    .wrap(Condition::new(compression_enabled, Compress::default()))
+                    .service(
+                        web::scope("/api/v1")
+                            .service(version)
+                            .configure(password::configure)
+                            .service(web::scope("/auth").configure(auth::configure))
+                            .service(web::scope("/passkeys").configure(passkey::configure)),
+                    )
+                    .configure(configure_admin),
+            )
+            .await
+        }
+    }
+
+    fn sign_up_request(mail: &str, password: &str) -> Request {
+        test::TestRequest::post()
+            .uri("/api/v1/sign-up")
+            .set_json(json!({ "name": "Test User", "mail": mail, "password": password }))
+            .to_request()
+    }
+
+    /// Authorizes `request` with the internal secret of [`TestFixtures::app`].
+    fn admin_request(request: test::TestRequest) -> Request {
+        let secret = test_app_config().internal_secret;
+        request
+            .insert_header((AUTHORIZATION, format!("Bearer {secret}")))
+            .to_request()
+    }
+
+    fn sign_in_request(mail: &str, password: &str) -> Request {
+        test::TestRequest::post()
+            .uri("/api/v1/sign-in")
+            .set_json(json!({ "mail": mail, "password": password }))
+            .to_request()
+    }
+
+    fn link_passkey_request(mail: &str, password: &str) -> Request {
+        test::TestRequest::post()
+            .uri("/api/v1/auth/passkey/link-to-account")
+            .set_json(json!({ "mail": mail, "password": password }))
+            .to_request()
+    }
+
+    #[sqlx::test]
+    async fn test_sign_up_created(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool.clone()).await;
+
+        let response = test::call_service(
+            &app,
+            sign_up_request("alice@example.com", "correct horse battery"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body: Value = test::read_body_json(response).await;
+        let user = Repository::get_by_mail(&pool, "alice@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(body["user_id"], user.id());
+    }
+
+    #[sqlx::test]
+    async fn test_sign_up_respects_app_configuration(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let mut app_config = test_app_config();
+        app_config.registration_enabled = false;
+        let app = fixtures.app_with_config(pool, app_config).await;
+
+        let response = test::call_service(
+            &app,
+            sign_up_request("alice@example.com", "correct horse battery"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[sqlx::test]
+    async fn test_sign_up_duplicate_mail(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        fixtures
+            .create_user(&pool, "alice@example.com", "password")
+            .await;
+        let app = fixtures.app(pool).await;
+
+        let response = test::call_service(
+            &app,
+            sign_up_request("alice@example.com", "correct horse battery"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["kind"], "AlreadyExists");
+    }
+
+    #[sqlx::test]
+    async fn test_sign_up_invalid_mail(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+
+        let response =
+            test::call_service(&app, sign_up_request("alice@", "correct horse battery")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["kind"], "ValidationError");
+        assert_eq!(body["message"], "Invalid email format");
+        assert_eq!(body["field"], "mail");
+    }
+
+    #[sqlx::test]
+    async fn test_sign_up_weak_password(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+
+        let response =
+            test::call_service(&app, sign_up_request("alice@example.com", "password")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["field"], "password");
+        assert_eq!(body["message"], "Password is too weak: Too common");
+    }
+
+    #[sqlx::test]
+    async fn test_sign_in_success(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        fixtures
+            .create_user(&pool, "alice@example.com", "password")
+            .await;
+        let app = fixtures.app(pool).await;
+
+        let response =
+            test::call_service(&app, sign_in_request("alice@example.com", "password")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["passkeys_registered"], false);
+    }
+
+    #[sqlx::test]
+    async fn test_sign_in_wrong_password(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        fixtures
+            .create_user(&pool, "alice@example.com", "password")
+            .await;
+        let app = fixtures.app(pool).await;
+
+        let response =
+            test::call_service(&app, sign_in_request("alice@example.com", "wrong")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["kind"], "AuthenticationFailure");
+    }
+
+    #[sqlx::test]
+    async fn test_sign_in_unknown_mail(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+
+        let response =
+            test::call_service(&app, sign_in_request("nobody@example.com", "password")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn test_locked_account_needs_the_password(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let user_id = fixtures
+            .create_user(&pool, "locked@example.com", "password")
+            .await;
+        let app = fixtures.app(pool).await;
+        let lock = || test::TestRequest::post().uri(&format!("/admin/users/{user_id}/lock"));
+
+        let response = test::call_service(&app, lock().to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&app, admin_request(lock())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response =
+            test::call_service(&app, sign_in_request("locked@example.com", "wrong")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response =
+            test::call_service(&app, sign_in_request("locked@example.com", "password")).await;
+        assert_eq!(response.status(), StatusCode::LOCKED);
+
+        let unlock = test::TestRequest::post().uri(&format!("/admin/users/{user_id}/unlock"));
+        let response = test::call_service(&app, admin_request(unlock)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response =
+            test::call_service(&app, sign_in_request("locked@example.com", "password")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn test_update_role_requires_internal_secret(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let user_id = fixtures
+            .create_user(&pool, "promoted@example.com", "password")
+            .await;
+        let app = fixtures.app(pool).await;
+        let update = |role: &str| {
+            test::TestRequest::patch()
+                .uri(&format!("/admin/users/{user_id}/role"))
+                .set_json(json!({ "role": role }))
+        };
+
+        let response = test::call_service(&app, update("admin").to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let request = update("admin")
+            .insert_header((AUTHORIZATION, "Bearer wrong"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = test::call_service(&app, admin_request(update("owner"))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::call_and_read_body_json(&app, admin_request(update("admin"))).await;
+        assert_eq!(body["role"], "admin");
+    }
+
+    #[sqlx::test]
+    async fn test_sign_in_passkey_only_account(pool: PgPool) {
+        let user = PasskeyUser {
+            id: Uuid::new_v4(),
+            mail: "passkey@example.com".into(),
+            name: "Passkey".into(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        PasskeyRepository::create_user(&pool, &user).await.unwrap();
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+
+        let response =
+            test::call_service(&app, sign_in_request("passkey@example.com", "password")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["kind"], "PasskeyOnly");
+    }
+
+    #[sqlx::test]
+    async fn test_sign_up_stores_handler_hash(pool: PgPool) {
+        let handler = MockPasswordHandler::default().with_hash(
+            "correct horse battery",
+            Method::SaltPepper,
+            "mock-hash",
+        );
+        let fixtures = TestFixtures::with_handler(Arc::new(handler));
+        let app = fixtures.app(pool.clone()).await;
+
+        let response = test::call_service(
+            &app,
+            sign_up_request("alice@example.com", "correct horse battery"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let user = Repository::get_by_mail(&pool, "alice@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.password_hash(), "mock-hash");
+    }
+
+    #[sqlx::test]
+    async fn test_sign_in_with_mock_handler(pool: PgPool) {
+        let handler =
+            MockPasswordHandler::default().with_hash("password", Method::SaltPepper, "mock-hash");
+        let fixtures = TestFixtures::with_handler(Arc::new(handler));
+        fixtures
+            .create_user(&pool, "alice@example.com", "password")
+            .await;
+        let app = fixtures.app(pool).await;
+
+        let response =
+            test::call_service(&app, sign_in_request("alice@example.com", "password")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response =
+            test::call_service(&app, sign_in_request("alice@example.com", "wrong")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn test_sign_up_hashes_with_configured_algorithm(pool: PgPool) {
+        let app_config = test_app_config();
+        let scrypt = ScryptParams::new(10, 8, 1, ScryptParams::RECOMMENDED_LEN).unwrap();
+        let algorithms = [
+            (HashAlgorithm::default(), "$argon2id$"),
+            (HashAlgorithm::Scrypt(scrypt), "$scrypt$"),
+        ];
+
+        for (index, (algorithm, prefix)) in algorithms.into_iter().enumerate() {
+            let handler = PasswordHandler::new(
+                10,
+                app_config.pepper.clone(),
+                app_config.pepper_history(),
+                algorithm,
+            );
+            let fixtures = TestFixtures::with_handler(Arc::new(handler));
+            let app = fixtures.app(pool.clone()).await;
+            let mail = format!("user{index}@example.com");
+
+            let response =
+                test::call_service(&app, sign_up_request(&mail, "correct horse battery")).await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let response =
+                test::call_service(&app, sign_in_request(&mail, "correct horse battery")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let user = Repository::get_by_mail(&pool, &mail)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(user.password_hash().starts_with(prefix));
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_link_passkey_upgrades_legacy_hash(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let user_id = fixtures
+            .create_user(&pool, "alice@example.com", "password")
+            .await;
+        let legacy = test_password_handler().hash("password", Method::SaltPepper);
+        Repository::update_password_hash(&pool, user_id, &legacy)
+            .await
+            .unwrap();
+        let app = fixtures.app(pool.clone()).await;
+
+        let response =
+            test::call_service(&app, link_passkey_request("alice@example.com", "wrong")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response =
+            test::call_service(&app, link_passkey_request("alice@example.com", "password")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let user = Repository::get_by_mail(&pool, "alice@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(user.password_hash().starts_with("$argon2id$"));
+    }
+
+    #[sqlx::test]
+    async fn test_unified_sign_in_password_only(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        fixtures
+            .create_user(&pool, "alice@example.com", "password")
+            .await;
+        let app = fixtures.app(pool).await;
+
+        let request = test::TestRequest::post()
+            .uri("/api/v1/auth/unified-sign-in")
+            .set_json(json!({ "mail": "alice@example.com" }))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body, json!({ "has_password": true, "has_passkeys": false }));
+    }
+
+    #[sqlx::test]
+    async fn test_version_is_versioned(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+
+        let request = test::TestRequest::get().uri("/api/v1/version").to_request();
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+
+        let request = test::TestRequest::get().uri("/version").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn test_user_credentials_pagination(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        for index in 0..3 {
+            fixtures
+                .create_user(&pool, &format!("user{index}@example.com"), "password")
+                .await;
+        }
+        let app = fixtures.app(pool).await;
+
+        let request =
+            admin_request(test::TestRequest::get().uri("/admin/user-credentials?page_size=2"));
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["items"].as_array().map(Vec::len), Some(2));
+        assert_eq!(body["filter_applied"], Value::Null);
+        let cursor = body["next_cursor"].as_i64().unwrap();
+
+        let request = admin_request(test::TestRequest::get().uri(&format!(
+            "/admin/user-credentials?page_size=2&cursor={cursor}"
+        )));
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["items"].as_array().map(Vec::len), Some(1));
+        assert_eq!(body["next_cursor"], Value::Null);
+    }
+
+    #[sqlx::test]
+    async fn test_user_credentials_filter(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        for mail in ["ada@example.com", "ADAM@example.com", "bob_ada@example.com"] {
+            fixtures.create_user(&pool, mail, "password").await;
+        }
+        let app = fixtures.app(pool).await;
+
+        let request = admin_request(test::TestRequest::get().uri("/admin/user-credentials?q=ada"));
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert_eq!(body["filter_applied"], "ada");
+        let mails: Vec<_> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|user| user["email"].as_str().unwrap())
+            .collect();
+        assert_eq!(mails, ["ada@example.com", "ADAM@example.com"]);
+    }
+
+    #[sqlx::test]
+    async fn test_user_credentials_requires_internal_secret(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/user-credentials")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let request = test::TestRequest::get()
+            .uri("/admin/user-credentials")
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test]
+    async fn test_responses_are_gzipped(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let mut app_config = test_app_config();
+        app_config.compression_enabled = true;
+        let app = fixtures.app_with_config(pool, app_config).await;
+
+        let request = test::TestRequest::get()
+            .uri("/api/v1/version")
+            .insert_header((header::ACCEPT_ENCODING, "gzip"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(
+            response.headers().get(header::CONTENT_ENCODING).unwrap(),
+            "gzip"
+        );
+
+        let body = test::read_body(response).await;
+        let mut json = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut json).unwrap();
+        let body: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[sqlx::test]
+    async fn test_invitation_token_is_returned_once(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+        let invite = || {
+            test::TestRequest::post()
+                .uri("/admin/invite")
+                .set_json(json!({ "email": "new@example.com", "role": "admin" }))
+        };
+
+        let response = test::call_service(&app, invite().to_request()).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = test::call_service(&app, admin_request(invite())).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["email"], "new@example.com");
+        let token = body["token"].as_str().unwrap();
+
+        let pending: Value = test::call_and_read_body_json(
+            &app,
+            admin_request(test::TestRequest::get().uri("/admin/invitations")),
+        )
+        .await;
+        assert_eq!(pending[0]["email"], "new@example.com");
+        assert!(pending[0].get("token").is_none());
+
+        let accept = test::TestRequest::post()
+            .uri("/api/v1/auth/accept-invite")
+            .set_json(json!({
+                "token": token,
+                "name": "New",
+                "password": "correct horse battery",
+            }))
+            .to_request();
+        let response = test::call_service(&app, accept).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[actix_web::test]
+    async fn test_openapi_lists_versioned_paths() {
+        let paths = ApiDoc::openapi().paths.paths;
+        assert!(paths.contains_key("/health"));
+        assert!(paths.contains_key("/api/v1/sign-in"));
+        assert!(paths.contains_key("/api/v1/auth/unified-sign-in"));
+        assert!(paths.contains_key("/api/v1/passkeys/start-registration"));
+        // Operator routes are only served on the internal port.
+        assert!(!paths.keys().any(|path| path.contains("/admin")));
+    }
+
+    fn base64url(bytes: &[u8]) -> Value {
+        serde_json::to_value(Base64UrlSafeData::from(bytes.to_vec())).unwrap()
+    }
+
+    fn discoverable_credential(challenge: &[u8], user_handle: Option<&[u8]>) -> Value {
+        let client_data = json!({
+            "type": "webauthn.get",
+            "challenge": base64url(challenge),
+            "origin": "http://localhost",
+        });
+        json!({
+            "public_key_credential": {
+                "id": "AQID",
+                "rawId": "AQID",
+                "type": "public-key",
+                "response": {
+                    "authenticatorData": "",
+                    "clientDataJSON": base64url(client_data.to_string().as_bytes()),
+                    "signature": "",
+                    "userHandle": user_handle.map(base64url),
+                },
+            }
+        })
+    }
+
+    #[sqlx::test]
+    async fn test_start_discoverable_authentication_has_no_user(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+
+        let request = test::TestRequest::post()
+            .uri("/api/v1/passkeys/start-discoverable-authentication")
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, request).await;
+        assert!(body["publicKey"]["challenge"].is_string());
+        assert!(body.get("user_id").is_none());
+        assert!(
+            body["publicKey"]["allowCredentials"]
+                .as_array()
+                .is_none_or(Vec::is_empty)
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_finish_discoverable_authentication_rejects_unknown_credentials(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+        let finish = |credential: Value| {
+            test::TestRequest::post()
+                .uri("/api/v1/passkeys/finish-discoverable-authentication")
+                .set_json(credential)
+                .to_request()
+        };
+
+        let without_handle = discoverable_credential(&[7; 32], None);
+        let response = test::call_service(&app, finish(without_handle)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let user_id = Uuid::new_v4();
+        let unknown_user = discoverable_credential(&[7; 32], Some(user_id.as_bytes()));
+        let response = test::call_service(&app, finish(unknown_user)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["message"], "Passkey does not exist");
+    }
+
+    #[sqlx::test]
+    async fn test_delete_passkey_requires_assertion(pool: PgPool) {
+        let user = PasskeyUser {
+            id: Uuid::new_v4(),
+            mail: "alice@example.com".into(),
+            name: "Alice".into(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        PasskeyRepository::create_user(&pool, &user).await.unwrap();
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+        let uri = format!("/api/v1/passkeys/01020304?user_id={}", user.id);
+
+        // The user id alone no longer identifies the owner.
+        let request = test::TestRequest::delete().uri(&uri).to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let request = test::TestRequest::delete()
+            .uri("/api/v1/passkeys/01020304")
+            .set_json(json!({
+                "user_id": user.id,
+                "session_token": "0".repeat(32),
+                "public_key_credential": {
+                    "id": "AQID",
+                    "rawId": "AQID",
+                    "type": "public-key",
+                    "response": {
+                        "authenticatorData": "",
+                        "clientDataJSON": "",
+                        "signature": "",
+                    },
+                },
+            }))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["message"], "Passkey authentication does not exist");
+    }
+
+    #[sqlx::test]
+    async fn test_finish_registration_requires_session_token(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+
+        let request = test::TestRequest::post()
+            .uri("/api/v1/passkeys/start-registration")
+            .set_json(json!({ "mail": "bound@example.com", "name": "Bound" }))
+            .to_request();
+        let challenge: Value = test::call_and_read_body_json(&app, request).await;
+        let session_token = challenge["session_token"].as_str().unwrap();
+        assert_eq!(session_token.len(), 32);
+
+        let finish = |session_token: &str| {
+            test::TestRequest::post()
+                .uri("/api/v1/passkeys/finish-registration")
+                .set_json(json!({
+                    "user_id": challenge["user_id"],
+                    "session_token": session_token,
+                    "register_public_key_credential": {
+                        "id": "AQID",
+                        "rawId": "AQID",
+                        "type": "public-key",
+                        "response": { "attestationObject": "", "clientDataJSON": "" },
+                    },
+                }))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, finish(&"0".repeat(32))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // The foreign attempt leaves the challenge for its owner.
+        let response = test::call_service(&app, finish(session_token)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn test_delete_user_requires_confirmation(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let user_id = fixtures
+            .create_user(&pool, "doomed@example.com", "password")
+            .await;
+        let other_id = fixtures
+            .create_user(&pool, "bystander@example.com", "password")
+            .await;
+        let app = fixtures.app(pool.clone()).await;
+        let delete = |uri: String| admin_request(test::TestRequest::delete().uri(&uri));
+
+        let request = test::TestRequest::delete()
+            .uri(&format!("/admin/users/{user_id}"))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = test::call_service(&app, delete(format!("/admin/users/{user_id}"))).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body: Value = test::read_body_json(response).await;
+        let token = body["confirmation_token"].as_str().unwrap().to_string();
+        assert!(body["expires_at"].is_string());
+
+        let response = test::call_service(
+            &app,
+            delete(format!("/admin/users/{other_id}?confirm={token}")),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = test::call_service(
+            &app,
+            delete(format!("/admin/users/{user_id}?confirm={}", Uuid::new_v4())),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = test::call_service(
+            &app,
+            delete(format!("/admin/users/{user_id}?confirm={token}")),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(
+            Repository::get_user_by_id(&pool, user_id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            Repository::get_user_by_id(&pool, other_id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        let response = test::call_service(&app, delete(format!("/admin/users/{user_id}"))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn test_start_authentication_by_mail_or_user_id(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+        let start = |body: Value| {
+            test::TestRequest::post()
+                .uri("/api/v1/passkeys/start-authentication")
+                .set_json(body)
+                .to_request()
+        };
+
+        let response = test::call_service(&app, start(json!({}))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let both = json!({ "mail": "ada@example.com", "user_id": Uuid::new_v4() });
+        let response = test::call_service(&app, start(both)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = test::call_service(&app, start(json!({ "user_id": Uuid::new_v4() }))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = test::call_service(&app, start(json!({ "mail": "ada@example.com" }))).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn test_start_registration_resident_key(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+        let start = |resident_key: &str| {
+            test::TestRequest::post()
+                .uri("/api/v1/passkeys/start-registration")
+                .set_json(json!({
+                    "mail": "resident@example.com",
+                    "name": "Resident",
+                    "resident_key": resident_key,
+                }))
+                .to_request()
+        };
+
+        let body: Value = test::call_and_read_body_json(&app, start("required")).await;
+        let selection = &body["creation_challenge_response"]["publicKey"]["authenticatorSelection"];
+        assert_eq!(selection["residentKey"], "required");
+        assert_eq!(selection["requireResidentKey"], true);
+
+        let response = test::call_service(&app, start("always")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn test_integrity_check_reports_users_without_passkeys(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool.clone()).await;
+        let get = || admin_request(test::TestRequest::get().uri("/admin/integrity-check"));
+
+        let response = test::call_service(&app, get()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let report: IntegrityReport = test::read_body_json(response).await;
+        assert_eq!(report.orphan_credentials, 0);
+        assert_eq!(report.users_with_no_credentials, 0);
+        assert_eq!(report.expired_challenges, 0);
+
+        let user = PasskeyUser {
+            id: Uuid::new_v4(),
+            mail: "alice@example.com".into(),
+            name: "Alice".into(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        PasskeyRepository::create_user(&pool, &user).await.unwrap();
+        let report: IntegrityReport =
+            test::read_body_json(test::call_service(&app, get()).await).await;
+        assert_eq!(report.users_with_no_credentials, 1);
+    }
+
+    #[sqlx::test]
+    async fn test_clear_challenge_store(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        let app = fixtures.app(pool).await;
+        let start_registration = test::TestRequest::post()
+            .uri("/api/v1/passkeys/start-registration")
+            .set_json(json!({ "mail": "alice@example.com", "name": "Alice" }))
+            .to_request();
+        let response = test::call_service(&app, start_registration).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let start_discoverable = test::TestRequest::post()
+            .uri("/api/v1/passkeys/start-discoverable-authentication")
+            .to_request();
+        let response = test::call_service(&app, start_discoverable).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let clear = || admin_request(test::TestRequest::delete().uri("/admin/challenge-store"));
+        let anonymous = test::TestRequest::delete()
+            .uri("/admin/challenge-store")
+            .to_request();
+        let response = test::call_service(&app, anonymous).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let cleared: ClearedChallenges = test::call_and_read_body_json(&app, clear()).await;
+        assert_eq!(cleared.purged_registrations, 1);
+        assert_eq!(cleared.purged_authentications, 0);
+        assert_eq!(cleared.purged_discoverable, 1);
+
+        let cleared: ClearedChallenges = test::call_and_read_body_json(&app, clear()).await;
+        assert_eq!(cleared.purged_registrations, 0);
+        assert_eq!(cleared.purged_discoverable, 0);
+    }
+
+    #[sqlx::test]
+    async fn test_sign_in_limits_failures_per_mail(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        fixtures
+            .create_user(&pool, "alice@example.com", "password")
+            .await;
+        fixtures
+            .create_user(&pool, "bob@example.com", "password")
+            .await;
+        let app = fixtures.app(pool).await;
+        let sign_in = |mail, password| test::call_service(&app, sign_in_request(mail, password));
+
+        for _ in 0..9 {
+            let response = sign_in("bob@example.com", "wrong").await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        // A successful sign-in starts over.
+        let response = sign_in("bob@example.com", "password").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = sign_in("bob@example.com", "wrong").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        for mail in ["alice@example.com", "Alice@Example.com"].repeat(5) {
+            let response = sign_in(mail, "wrong").await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = sign_in("alice@example.com", "password").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body["kind"], "LimitExceeded");
+
+        let response = sign_in("bob@example.com", "password").await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[sqlx::test]
+    async fn test_link_passkey_limits_failures_per_mail(pool: PgPool) {
+        let fixtures = TestFixtures::new();
+        fixtures
+            .create_user(&pool, "alice@example.com", "password")
+            .await;
+        let app = fixtures.app(pool).await;
+
+        for _ in 0..10 {
+            let response =
+                test::call_service(&app, link_passkey_request("alice@example.com", "wrong")).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response =
+            test::call_service(&app, link_passkey_request("alice@example.com", "wrong")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+        // Sign-in and linking share the limit.
+        let response =
+            test::call_service(&app, sign_in_request("alice@example.com", "password")).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
 }
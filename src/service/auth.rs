@@ -0,0 +1,341 @@
+use actix_web::{HttpRequest, HttpResponse, Responder, post, web};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use utoipa::{OpenApi, ToSchema};
+use webauthn_rs::{
+    Webauthn,
+    prelude::{RequestChallengeResponse, Uuid},
+};
+
+use crate::{
+    config::WebauthnPolicy,
+    crypto::{PasswordHandler, PasswordHandlerTrait, PasswordStrengthConfig},
+    error::Error,
+    repository::{AuditAction, InvitationRepository, PasskeyRepository, PasskeyUser, Repository},
+    store::ChallengeStore,
+};
+
+use super::{
+    ErrorKind, FieldError, ServiceError, hash_invitation_token, log_audit_event,
+    passkey::{
+        self, PasskeyCreationChallenge, PendingPasskeyAuthentication, PendingPasskeyRegistration,
+    },
+    password::{self, FailedSignIns},
+};
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct UnifiedSignIn {
+    mail: String,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+struct UnifiedSignInResponse {
+    has_password: bool,
+    has_passkeys: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    passkey_challenge: Option<RequestChallengeResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_token: Option<String>,
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Available sign-in methods", body = UnifiedSignInResponse),
+        (status = 423, description = "Account is suspended", body = ServiceError),
+        (status = 429, description = "Too many pending challenges", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[post("/unified-sign-in")]
+async fn unified_sign_in(
+    sign_in: web::Json<UnifiedSignIn>,
+    pool: web::ThinData<PgPool>,
+    webauthn: web::Data<Webauthn>,
+    webauthn_policy: web::Data<WebauthnPolicy>,
+    authentication_store: web::Data<ChallengeStore<PendingPasskeyAuthentication>>,
+) -> impl Responder {
+    let (password_user, passkey_user, locked) = match tokio::try_join!(
+        Repository::get_by_mail(&pool, &sign_in.mail),
+        PasskeyRepository::get_user_by_mail(&pool, &sign_in.mail),
+        Repository::is_locked_by_mail(&pool, &sign_in.mail),
+    ) {
+        Ok(result) => result,
+        Err(_) => return ServiceError::internal_server_error(),
+    };
+    if locked {
+        return ServiceError::account_suspended();
+    }
+
+    let mut response = UnifiedSignInResponse {
+        has_password: password_user.is_some(),
+        ..UnifiedSignInResponse::default()
+    };
+
+    if let Some(user) = passkey_user {
+        let passkeys = match PasskeyRepository::get_user_credentials(&pool, user.id()).await {
+            Ok(passkeys) => passkeys,
+            Err(_) => return ServiceError::internal_server_error(),
+        };
+
+        if !passkeys.is_empty() {
+            match passkey::begin_authentication(
+                &webauthn,
+                &webauthn_policy,
+                &authentication_store,
+                *user.id(),
+                &passkeys,
+            ) {
+                Ok((challenge, session_token)) => {
+                    response.has_passkeys = true;
+                    response.passkey_challenge = Some(challenge);
+                    response.user_id = Some(*user.id());
+                    response.session_token = Some(session_token);
+                }
+                Err(err) => return err,
+            }
+        }
+    }
+
+    HttpResponse::Ok().json(response)
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct AcceptInvite {
+    token: String,
+    name: String,
+    password: String,
+}
+
+#[utoipa::path(
+    responses(
+        (status = 201, description = "Account created"),
+        (status = 400, description = "Password is too weak", body = FieldError),
+        (status = 404, description = "Invitation does not exist or has expired", body = ServiceError),
+        (status = 409, description = "User already exists", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[post("/accept-invite")]
+async fn accept_invite(
+    request: HttpRequest,
+    invite: web::Json<AcceptInvite>,
+    pool: web::ThinData<PgPool>,
+    handler: web::Data<dyn PasswordHandlerTrait>,
+    password_strength: web::Data<PasswordStrengthConfig>,
+) -> impl Responder {
+    if let Err(violations) = PasswordHandler::is_strong(&invite.password, &password_strength) {
+        return ServiceError::weak_password(violations);
+    }
+
+    let result = InvitationRepository::accept(
+        &pool,
+        &hash_invitation_token(&invite.token),
+        &invite.name,
+        &invite.password,
+        handler.as_ref(),
+    )
+    .await;
+
+    match result {
+        Ok(Some(accepted)) => {
+            log_audit_event(
+                &pool,
+                &request,
+                AuditAction::SignUp,
+                Some(accepted.user_id),
+                json!({ "invited": true, "role": accepted.role }),
+            );
+            HttpResponse::Created().finish()
+        }
+        Ok(None) => ServiceError {
+            kind: ErrorKind::DoesNotExist,
+            message: "Invitation does not exist or has expired".into(),
+        }
+        .into_response(),
+        Err(Error::UniqueViolation) => ServiceError {
+            kind: ErrorKind::AlreadyExists,
+            message: "User already exists".into(),
+        }
+        .into_response(),
+        Err(err) => ServiceError::from(err).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct LinkPasskeyToAccount {
+    mail: String,
+    password: String,
+}
+
+/// Starts a passkey registration for an existing password account. The
+/// service has no sessions, so the account proves ownership with its
+/// password. A passkey user with the account's mail is created and linked if
+/// there is none yet, so finishing the registration never creates a
+/// duplicate user.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Registration challenge", body = PasskeyCreationChallenge),
+        (status = 401, description = "Wrong mail or password", body = ServiceError),
+        (status = 423, description = "Account is suspended", body = ServiceError),
+        (status = 429, description = "Too many failed sign-ins for this mail address or pending challenges", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[post("/passkey/link-to-account")]
+#[allow(clippy::too_many_arguments)]
+async fn link_passkey_to_account(
+    request: HttpRequest,
+    link: web::Json<LinkPasskeyToAccount>,
+    pool: web::ThinData<PgPool>,
+    handler: web::Data<dyn PasswordHandlerTrait>,
+    webauthn: web::Data<Webauthn>,
+    webauthn_policy: web::Data<WebauthnPolicy>,
+    registration_store: web::Data<ChallengeStore<PendingPasskeyRegistration>>,
+    failed_sign_ins: web::Data<FailedSignIns>,
+) -> impl Responder {
+    let mail_hash = FailedSignIns::key(&link.mail);
+    if let Some(retry_after) = failed_sign_ins.retry_after(&mail_hash) {
+        log_audit_event(
+            &pool,
+            &request,
+            AuditAction::RateLimitExceededEmail,
+            None,
+            json!({ "mail_hash": hex::encode(mail_hash) }),
+        );
+        return ServiceError::rate_limited(retry_after);
+    }
+
+    let account = match Repository::get_by_mail(&pool, &link.mail).await {
+        Ok(Some(account)) => account,
+        Ok(None) => {
+            let _ = handler.verify(&link.password, handler.dummy_hash(), handler.hash_method());
+            failed_sign_ins.record_failure(mail_hash);
+            return ServiceError {
+                kind: ErrorKind::AuthenticationFailure,
+                message: "Failed to authenticate".into(),
+            }
+            .into_response();
+        }
+        Err(_) => return ServiceError::internal_server_error(),
+    };
+    if !password::verify_password(&pool, handler.as_ref(), &account, &link.password).await {
+        failed_sign_ins.record_failure(mail_hash);
+        log_audit_event(
+            &pool,
+            &request,
+            AuditAction::SignInFailure,
+            Some(account.id()),
+            json!({ "passkey_link": true }),
+        );
+        return ServiceError {
+            kind: ErrorKind::AuthenticationFailure,
+            message: "Failed to authenticate".into(),
+        }
+        .into_response();
+    }
+    failed_sign_ins.reset(&mail_hash);
+    if !account.is_active() {
+        return ServiceError::account_suspended();
+    }
+
+    let user = match PasskeyRepository::get_user_by_mail(&pool, account.email()).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            let now = Utc::now();
+            let user = PasskeyUser {
+                id: Uuid::new_v4(),
+                mail: account.email().to_owned(),
+                name: account.name().to_owned(),
+                created_at: now,
+                updated_at: now,
+            };
+            if PasskeyRepository::create_user(&*pool, &user).await.is_err() {
+                return ServiceError::internal_server_error();
+            }
+            user
+        }
+        Err(_) => return ServiceError::internal_server_error(),
+    };
+    if PasskeyRepository::link_existing_user(&pool, user.id(), account.id())
+        .await
+        .is_err()
+    {
+        return ServiceError::internal_server_error();
+    }
+    let credentials = match PasskeyRepository::get_user_credential_ids(&pool, user.id()).await {
+        Ok(credentials) => credentials,
+        Err(_) => return ServiceError::internal_server_error(),
+    };
+
+    let user_id = *user.id();
+    match passkey::begin_registration(
+        &webauthn,
+        &webauthn_policy,
+        &registration_store,
+        user,
+        Some(credentials),
+        |_| {},
+    ) {
+        Ok((creation_challenge_response, session_token)) => {
+            HttpResponse::Ok().json(PasskeyCreationChallenge {
+                user_id,
+                session_token,
+                creation_challenge_response,
+                hints: Vec::new(),
+                authenticator_attachment: None,
+            })
+        }
+        Err(err) => err,
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(unified_sign_in, accept_invite, link_passkey_to_account))]
+pub(super) struct AuthApi;
+
+pub(super) fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(unified_sign_in)
+        .service(accept_invite)
+        .service(link_passkey_to_account);
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_request_types_reject_unknown_fields() {
+        let sign_in: UnifiedSignIn =
+            serde_json::from_value(json!({ "mail": "ada@example.com" })).unwrap();
+        assert_eq!(sign_in.mail, "ada@example.com");
+
+        let unknown = json!({ "mail": "ada@example.com", "password": "x" });
+        assert!(serde_json::from_value::<UnifiedSignIn>(unknown).is_err());
+        let unknown = json!({ "token": "t", "name": "Ada", "password": "x", "role": "admin" });
+        assert!(serde_json::from_value::<AcceptInvite>(unknown).is_err());
+        let unknown = json!({ "mail": "ada@example.com", "password": "x", "user_id": 1 });
+        assert!(serde_json::from_value::<LinkPasskeyToAccount>(unknown).is_err());
+    }
+
+    #[test]
+    fn test_unified_sign_in_response_omits_missing_challenge() {
+        let response = UnifiedSignInResponse {
+            has_password: true,
+            ..UnifiedSignInResponse::default()
+        };
+        assert_eq!(
+            serde_json::to_value(response).unwrap(),
+            json!({ "has_password": true, "has_passkeys": false })
+        );
+    }
+}
@@ -0,0 +1,997 @@
+use std::sync::Arc;
+
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, post, web};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use utoipa::{OpenApi, ToSchema};
+use webauthn_rs::{
+    Webauthn,
+    prelude::{
+        AuthenticationResult, CreationChallengeResponse, CredentialID, DiscoverableAuthentication,
+        DiscoverableKey, Passkey, PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
+        RegisterPublicKeyCredential, RequestChallengeResponse, Uuid,
+    },
+};
+
+use webauthn_rs_proto::{
+    AuthenticatorAttachment, CollectedClientData, PublicKeyCredentialCreationOptions,
+    PublicKeyCredentialHints, ResidentKeyRequirement,
+};
+
+use crate::{
+    config::{AccountPolicy, AppConfiguration, WebauthnPolicy},
+    error::Error,
+    middleware::idempotency::IdempotencyMiddleware,
+    repository::{AuditAction, PasskeyRepository, PasskeyUser, Repository},
+    store::{Challenge, ChallengeStore, Insertion},
+    validation::validate_email,
+};
+
+use log::{Level, log};
+
+use super::{ErrorKind, FieldError, ServiceError, log_audit_event, secrets_match};
+
+pub struct PendingPasskeyRegistration {
+    session_token: String,
+    resident_key_required: bool,
+    new_user: Option<PasskeyUser>,
+    registration: PasskeyRegistration,
+}
+
+pub struct PendingPasskeyAuthentication {
+    session_token: String,
+    authentication: PasskeyAuthentication,
+}
+
+/// Random token binding a pending ceremony to the client that started it.
+/// The user id alone is not secret, so finishing also requires this token.
+fn session_token() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct StartPasskeyRegistration {
+    mail: String,
+    name: String,
+    hints: Option<Vec<String>>,
+    authenticator_attachment: Option<String>,
+    resident_key: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct PasskeyCreationChallenge {
+    pub(super) user_id: Uuid,
+    pub(super) session_token: String,
+    #[schema(value_type = Object)]
+    pub(super) creation_challenge_response: CreationChallengeResponse,
+    pub(super) hints: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) authenticator_attachment: Option<String>,
+}
+
+fn parse_hint(hint: &str) -> Option<PublicKeyCredentialHints> {
+    match hint {
+        "security-key" => Some(PublicKeyCredentialHints::SecurityKey),
+        "client-device" => Some(PublicKeyCredentialHints::ClientDevice),
+        "hybrid" => Some(PublicKeyCredentialHints::Hybrid),
+        _ => None,
+    }
+}
+
+fn parse_resident_key(resident_key: &str) -> Option<ResidentKeyRequirement> {
+    match resident_key {
+        "required" => Some(ResidentKeyRequirement::Required),
+        "preferred" => Some(ResidentKeyRequirement::Preferred),
+        "discouraged" => Some(ResidentKeyRequirement::Discouraged),
+        _ => None,
+    }
+}
+
+fn parse_authenticator_attachment(attachment: &str) -> Option<AuthenticatorAttachment> {
+    match attachment {
+        "platform" => Some(AuthenticatorAttachment::Platform),
+        "cross-platform" => Some(AuthenticatorAttachment::CrossPlatform),
+        _ => None,
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Registration challenge", body = PasskeyCreationChallenge),
+        (status = 400, description = "Invalid mail, hints, attachment or resident key", body = FieldError),
+        (status = 403, description = "Mail domain is not allowed", body = ServiceError),
+        (status = 429, description = "Too many pending challenges", body = ServiceError),
+        (status = 503, description = "Registration is disabled", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[post("/start-registration")]
+async fn start_passkey_registration(
+    registration: web::Json<StartPasskeyRegistration>,
+    pool: web::ThinData<PgPool>,
+    webauthn: web::Data<Webauthn>,
+    webauthn_policy: web::Data<WebauthnPolicy>,
+    registration_store: web::Data<ChallengeStore<PendingPasskeyRegistration>>,
+    app_config: web::ThinData<Arc<AppConfiguration>>,
+    account_policy: web::Data<AccountPolicy>,
+) -> impl Responder {
+    if !app_config.registration_enabled {
+        return ServiceError::registration_disabled();
+    }
+    if let Err(err) = validate_email(&registration.mail) {
+        return ServiceError::invalid_field("mail", err.to_string());
+    }
+    if !account_policy.is_mail_allowed(&registration.mail) {
+        return ServiceError::domain_not_allowed();
+    }
+
+    let mut hints = Vec::new();
+    let mut credential_hints = Vec::new();
+    for hint in registration.hints.iter().flatten() {
+        let Some(credential_hint) = parse_hint(hint) else {
+            return ServiceError {
+                kind: ErrorKind::ValidationError,
+                message: format!(
+                    "Unknown hint `{hint}`, expected security-key, client-device or hybrid"
+                ),
+            }
+            .into_response();
+        };
+        if !hints.contains(hint) {
+            hints.push(hint.clone());
+            credential_hints.push(credential_hint);
+        }
+    }
+    let authenticator_attachment = match registration.authenticator_attachment.as_deref() {
+        Some(attachment) => match parse_authenticator_attachment(attachment) {
+            Some(attachment) => Some(attachment),
+            None => {
+                return ServiceError {
+                    kind: ErrorKind::ValidationError,
+                    message: "Authenticator attachment must be platform or cross-platform".into(),
+                }
+                .into_response();
+            }
+        },
+        None => None,
+    };
+    let resident_key = match registration.resident_key.as_deref() {
+        Some(resident_key) => match parse_resident_key(resident_key) {
+            Some(resident_key) => Some(resident_key),
+            None => {
+                return ServiceError {
+                    kind: ErrorKind::ValidationError,
+                    message: "Resident key must be required, preferred or discouraged".into(),
+                }
+                .into_response();
+            }
+        },
+        None => None,
+    };
+
+    let (user, credentials) =
+        match PasskeyRepository::get_user_by_mail(&pool, &registration.mail).await {
+            Ok(Some(user)) => {
+                let credentials =
+                    match PasskeyRepository::get_user_credential_ids(&pool, user.id()).await {
+                        Ok(credentials) => credentials,
+                        Err(_) => return ServiceError::internal_server_error(),
+                    };
+                (user, Some(credentials))
+            }
+            Ok(None) => {
+                let now = Utc::now();
+                let new_user = PasskeyUser {
+                    id: Uuid::new_v4(),
+                    mail: registration.mail.clone(),
+                    name: registration.name.clone(),
+                    created_at: now,
+                    updated_at: now,
+                };
+                (new_user, None)
+            }
+            Err(_) => return ServiceError::internal_server_error(),
+        };
+
+    let user_id = *user.id();
+    let result = begin_registration(
+        &webauthn,
+        &webauthn_policy,
+        &registration_store,
+        user,
+        credentials,
+        |public_key| {
+            if let Some(selection) = public_key.authenticator_selection.as_mut() {
+                selection.authenticator_attachment = authenticator_attachment;
+                if let Some(resident_key) = resident_key {
+                    selection.require_resident_key =
+                        matches!(resident_key, ResidentKeyRequirement::Required);
+                    selection.resident_key = Some(resident_key);
+                }
+            }
+            // Hints only steer the browser's authenticator picker; any
+            // authenticator may still answer the challenge.
+            if !credential_hints.is_empty() {
+                public_key.hints = Some(credential_hints);
+            }
+        },
+    );
+
+    match result {
+        Ok((creation_challenge_response, session_token)) => {
+            HttpResponse::Ok().json(PasskeyCreationChallenge {
+                user_id,
+                session_token,
+                creation_challenge_response,
+                hints,
+                authenticator_attachment: registration.authenticator_attachment.clone(),
+            })
+        }
+        Err(err) => err,
+    }
+}
+
+/// Starts a passkey registration ceremony for `user` and stores its state
+/// until the matching finish request arrives. `credentials` lists the
+/// passkeys an existing user already holds; `None` means `user` is new and is
+/// only persisted once registration finishes. Returns the challenge and the
+/// session token the finish request has to present.
+pub(super) fn begin_registration(
+    webauthn: &Webauthn,
+    webauthn_policy: &WebauthnPolicy,
+    registration_store: &ChallengeStore<PendingPasskeyRegistration>,
+    user: PasskeyUser,
+    credentials: Option<Vec<CredentialID>>,
+    customize: impl FnOnce(&mut PublicKeyCredentialCreationOptions),
+) -> Result<(CreationChallengeResponse, String), HttpResponse> {
+    let new_user = credentials.is_none();
+    let (mut creation_challenge_response, passkey_registration) = webauthn
+        .start_passkey_registration(*user.id(), user.mail(), &user.name, credentials)
+        .map_err(|_| ServiceError::internal_server_error())?;
+    let public_key = &mut creation_challenge_response.public_key;
+    public_key.attestation = Some(webauthn_policy.attestation.clone());
+    if let Some(selection) = public_key.authenticator_selection.as_mut() {
+        selection.user_verification = webauthn_policy.user_verification;
+    }
+    customize(public_key);
+    let resident_key_required = public_key
+        .authenticator_selection
+        .as_ref()
+        .is_some_and(|selection| selection.require_resident_key);
+    log!(
+        Level::Info,
+        "Issued Challenge: {:?}",
+        creation_challenge_response,
+    );
+
+    let user_id = *user.id();
+    let session_token = session_token();
+    let pending_registration = PendingPasskeyRegistration {
+        session_token: session_token.clone(),
+        resident_key_required,
+        new_user: new_user.then_some(user),
+        registration: passkey_registration,
+    };
+
+    match registration_store.insert(user_id, pending_registration) {
+        Insertion::Stored => Ok((creation_challenge_response, session_token)),
+        Insertion::Full => Err(ServiceError::limit_exceeded()),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct FinishPasskeyRegistration {
+    user_id: Uuid,
+    session_token: String,
+    #[schema(value_type = Object)]
+    register_public_key_credential: RegisterPublicKeyCredential,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct PasskeyRegistered {
+    user_id: Uuid,
+}
+
+#[utoipa::path(
+    params(("Idempotency-Key" = Option<Uuid>, Header, description = "Replays the stored response when a request is retried")),
+    responses(
+        (status = 201, description = "Passkey registered", body = PasskeyRegistered),
+        (status = 400, description = "Attestation could not be verified", body = ServiceError),
+        (status = 404, description = "Registration does not exist", body = ServiceError),
+        (status = 408, description = "Challenge has expired", body = ServiceError),
+        (status = 409, description = "Passkey or user already exists", body = ServiceError),
+        (status = 422, description = "A resident key was required but not created", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[post("/finish-registration", wrap = "IdempotencyMiddleware")]
+async fn finish_passkey_registration(
+    request: HttpRequest,
+    registration: web::Json<FinishPasskeyRegistration>,
+    pool: web::ThinData<PgPool>,
+    webauthn: web::Data<Webauthn>,
+    registration_store: web::Data<ChallengeStore<PendingPasskeyRegistration>>,
+) -> impl Responder {
+    let pending_registration = match registration_store.take_if(&registration.user_id, |pending| {
+        secrets_match(&pending.session_token, &registration.session_token)
+    }) {
+        Challenge::Valid(pending_registration) => pending_registration,
+        Challenge::Expired => return ServiceError::challenge_expired(),
+        Challenge::Missing => {
+            return ServiceError {
+                kind: ErrorKind::DoesNotExist,
+                message: "Passkey registration does not exist".into(),
+            }
+            .into_response();
+        }
+    };
+
+    let passkey = match webauthn.finish_passkey_registration(
+        &registration.register_public_key_credential,
+        &pending_registration.registration,
+    ) {
+        Ok(passkey) => passkey,
+        Err(err) => {
+            log!(Level::Error, "{err}");
+            return ServiceError {
+                kind: ErrorKind::ValidationError,
+                message: "Failed to authenticate passkey".into(),
+            }
+            .into_response();
+        }
+    };
+
+    // The attestation does not say whether the key is resident, so this
+    // relies on the browser's unsigned credProps report.
+    let resident_key_created = registration
+        .register_public_key_credential
+        .extensions
+        .cred_props
+        .as_ref()
+        .and_then(|cred_props| cred_props.rk)
+        == Some(true);
+    if pending_registration.resident_key_required && !resident_key_created {
+        return ServiceError {
+            kind: ErrorKind::ResidentKeyMissing,
+            message: "A discoverable credential was required, but the authenticator did not report creating one".into(),
+        }
+        .into_response();
+    }
+
+    match PasskeyRepository::create_user_credentials_in_transaction(
+        &pool,
+        pending_registration.new_user.as_ref(),
+        &registration.user_id,
+        &passkey,
+    )
+    .await
+    {
+        Ok(_) => {
+            log_audit_event(
+                &pool,
+                &request,
+                AuditAction::PasskeyRegistered,
+                None,
+                json!({ "passkey_user_id": registration.user_id }),
+            );
+            HttpResponse::Created().json(PasskeyRegistered {
+                user_id: registration.user_id,
+            })
+        }
+        Err(err) => {
+            if err.is_unique_violation() {
+                ServiceError {
+                    kind: ErrorKind::AlreadyExists,
+                    message: "Credential id already exists".into(),
+                }
+                .into_response()
+            } else {
+                log!(Level::Error, "Credential creation: {err}");
+                ServiceError::internal_server_error()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct StartPasskeyAuthentication {
+    mail: Option<String>,
+    user_id: Option<Uuid>,
+}
+
+impl StartPasskeyAuthentication {
+    /// Looks the user up by whichever identifier was given. Clients that
+    /// only kept the `user_id` from registration can sign in without the
+    /// mail.
+    async fn resolve_user(&self, pool: &PgPool) -> Result<Option<PasskeyUser>, Error> {
+        match (&self.mail, &self.user_id) {
+            (Some(mail), None) => PasskeyRepository::get_user_by_mail(pool, mail).await,
+            (None, Some(user_id)) => PasskeyRepository::get_user_by_id(pool, user_id).await,
+            _ => Err(Error::Other(
+                "Exactly one of mail and user_id is required".into(),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct PasskeyRequestChallenge {
+    user_id: Uuid,
+    session_token: String,
+    #[schema(value_type = Object)]
+    request_challenge_response: RequestChallengeResponse,
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Authentication challenge", body = PasskeyRequestChallenge),
+        (status = 400, description = "Not exactly one of mail and user_id given", body = ServiceError),
+        (status = 404, description = "User does not exist", body = ServiceError),
+        (status = 423, description = "Account is suspended", body = ServiceError),
+        (status = 429, description = "Too many pending challenges", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[post("/start-authentication")]
+async fn start_passkey_authentication(
+    authentication: web::Json<StartPasskeyAuthentication>,
+    pool: web::ThinData<PgPool>,
+    webauthn: web::Data<Webauthn>,
+    webauthn_policy: web::Data<WebauthnPolicy>,
+    authentication_store: web::Data<ChallengeStore<PendingPasskeyAuthentication>>,
+) -> impl Responder {
+    if authentication.mail.is_some() == authentication.user_id.is_some() {
+        return ServiceError {
+            kind: ErrorKind::ValidationError,
+            message: "Exactly one of mail and user_id is required".into(),
+        }
+        .into_response();
+    }
+
+    let user = match authentication.resolve_user(&pool).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return ServiceError {
+                kind: ErrorKind::DoesNotExist,
+                message: "User does not exist".into(),
+            }
+            .into_response();
+        }
+        Err(_) => return ServiceError::internal_server_error(),
+    };
+    match Repository::is_locked_by_mail(&pool, user.mail()).await {
+        Ok(true) => return ServiceError::account_suspended(),
+        Ok(false) => {}
+        Err(_) => return ServiceError::internal_server_error(),
+    }
+
+    let user_id = *user.id();
+
+    let passkeys = match PasskeyRepository::get_user_credentials(&pool, &user_id).await {
+        Ok(passkeys) => passkeys,
+        Err(_) => return ServiceError::internal_server_error(),
+    };
+
+    match begin_authentication(
+        &webauthn,
+        &webauthn_policy,
+        &authentication_store,
+        user_id,
+        &passkeys,
+    ) {
+        Ok((request_challenge_response, session_token)) => {
+            HttpResponse::Ok().json(PasskeyRequestChallenge {
+                user_id,
+                session_token,
+                request_challenge_response,
+            })
+        }
+        Err(response) => response,
+    }
+}
+
+/// Starts a passkey authentication ceremony for `passkeys` and stores its
+/// state under `user_id` until the matching finish request arrives. Returns
+/// the challenge and the session token the finish request has to present.
+pub(super) fn begin_authentication(
+    webauthn: &Webauthn,
+    webauthn_policy: &WebauthnPolicy,
+    authentication_store: &ChallengeStore<PendingPasskeyAuthentication>,
+    user_id: Uuid,
+    passkeys: &[Passkey],
+) -> Result<(RequestChallengeResponse, String), HttpResponse> {
+    let (mut request_challenge_response, passkey_authentication) = webauthn
+        .start_passkey_authentication(passkeys)
+        .map_err(|_| ServiceError::internal_server_error())?;
+    request_challenge_response.public_key.user_verification = webauthn_policy.user_verification;
+
+    let session_token = session_token();
+    let pending_authentication = PendingPasskeyAuthentication {
+        session_token: session_token.clone(),
+        authentication: passkey_authentication,
+    };
+
+    match authentication_store.insert(user_id, pending_authentication) {
+        Insertion::Stored => Ok((request_challenge_response, session_token)),
+        Insertion::Full => Err(ServiceError::limit_exceeded()),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct FinishPasskeyAuthentication {
+    user_id: Uuid,
+    session_token: String,
+    #[schema(value_type = Object)]
+    public_key_credential: PublicKeyCredential,
+}
+
+#[utoipa::path(
+    params(("Idempotency-Key" = Option<Uuid>, Header, description = "Replays the stored response when a request is retried")),
+    responses(
+        (status = 200, description = "Authenticated"),
+        (status = 401, description = "Assertion could not be verified", body = ServiceError),
+        (status = 404, description = "Authentication does not exist", body = ServiceError),
+        (status = 408, description = "Challenge has expired", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[post("/finish-authentication", wrap = "IdempotencyMiddleware")]
+async fn finish_passkey_authentication(
+    request: HttpRequest,
+    authentication: web::Json<FinishPasskeyAuthentication>,
+    pool: web::ThinData<PgPool>,
+    webauthn: web::Data<Webauthn>,
+    authentication_store: web::Data<ChallengeStore<PendingPasskeyAuthentication>>,
+) -> impl Responder {
+    if let Err(response) = verify_assertion(
+        &request,
+        &pool,
+        &webauthn,
+        &authentication_store,
+        &authentication,
+    )
+    .await
+    {
+        return response;
+    }
+
+    log_audit_event(
+        &pool,
+        &request,
+        AuditAction::PasskeySignInSuccess,
+        None,
+        json!({ "passkey_user_id": authentication.user_id }),
+    );
+    HttpResponse::Ok().finish()
+}
+
+/// Completes the authentication started for `authentication.user_id`. Proves
+/// that the caller holds one of that user's passkeys, since the user id
+/// alone is not secret.
+async fn verify_assertion(
+    request: &HttpRequest,
+    pool: &PgPool,
+    webauthn: &Webauthn,
+    authentication_store: &ChallengeStore<PendingPasskeyAuthentication>,
+    authentication: &FinishPasskeyAuthentication,
+) -> Result<AuthenticationResult, HttpResponse> {
+    let pending_authentication = match authentication_store
+        .take_if(&authentication.user_id, |pending| {
+            secrets_match(&pending.session_token, &authentication.session_token)
+        }) {
+        Challenge::Valid(pending_authentication) => pending_authentication,
+        Challenge::Expired => return Err(ServiceError::challenge_expired()),
+        Challenge::Missing => {
+            return Err(ServiceError {
+                kind: ErrorKind::DoesNotExist,
+                message: "Passkey authentication does not exist".into(),
+            }
+            .into_response());
+        }
+    };
+
+    let result = match webauthn.finish_passkey_authentication(
+        &authentication.public_key_credential,
+        &pending_authentication.authentication,
+    ) {
+        Ok(result) => result,
+        Err(err) => {
+            log_audit_event(
+                pool,
+                request,
+                AuditAction::PasskeySignInFailure,
+                None,
+                json!({ "passkey_user_id": authentication.user_id }),
+            );
+            return Err(ServiceError::passkey_authentication_failure(&err));
+        }
+    };
+
+    let passkey = match PasskeyRepository::get_credential_by_id(pool, result.cred_id()).await {
+        Ok(Some(credential)) => credential.credential,
+        Ok(None) => {
+            return Err(ServiceError {
+                kind: ErrorKind::DoesNotExist,
+                message: "Passkey does not exist".into(),
+            }
+            .into_response());
+        }
+        Err(_) => return Err(ServiceError::internal_server_error()),
+    };
+    if store_updated_credential(pool, passkey, &result)
+        .await
+        .is_err()
+    {
+        return Err(ServiceError::internal_server_error());
+    }
+
+    Ok(result)
+}
+
+/// Persists the counter and backup state reported by a successful
+/// authentication. Most synced passkeys never change, so nothing is written
+/// unless `webauthn_rs` reports an update.
+async fn store_updated_credential(
+    pool: &PgPool,
+    mut passkey: Passkey,
+    result: &AuthenticationResult,
+) -> Result<(), Error> {
+    if passkey.update_credential(result) == Some(true) {
+        PasskeyRepository::update_credential(pool, result.cred_id(), &passkey).await?;
+    }
+
+    Ok(())
+}
+
+/// Key of a pending discoverable authentication. The user is unknown until
+/// the browser answers, so the state is stored under the challenge, which
+/// the browser echoes back in `clientDataJSON`.
+fn discoverable_challenge_key(challenge: &[u8]) -> Uuid {
+    let digest = Sha256::digest(challenge);
+    Uuid::from_slice(&digest[..16]).expect("Sha256 digest is longer than a UUID")
+}
+
+fn echoed_challenge_key(credential: &PublicKeyCredential) -> Option<Uuid> {
+    let client_data: CollectedClientData =
+        serde_json::from_slice(credential.response.client_data_json.as_ref()).ok()?;
+    Some(discoverable_challenge_key(client_data.challenge.as_ref()))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Authentication challenge without allowed credentials", body = Object),
+        (status = 429, description = "Too many pending challenges", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[post("/start-discoverable-authentication")]
+async fn start_discoverable_authentication(
+    webauthn: web::Data<Webauthn>,
+    discoverable_store: web::Data<ChallengeStore<DiscoverableAuthentication>>,
+) -> impl Responder {
+    let (request_challenge_response, discoverable_authentication) =
+        match webauthn.start_discoverable_authentication() {
+            Ok(result) => result,
+            Err(_) => {
+                return ServiceError::internal_server_error();
+            }
+        };
+
+    let key = discoverable_challenge_key(request_challenge_response.public_key.challenge.as_ref());
+    match discoverable_store.insert(key, discoverable_authentication) {
+        Insertion::Stored => HttpResponse::Ok().json(request_challenge_response),
+        Insertion::Full => ServiceError::limit_exceeded(),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct FinishDiscoverableAuthentication {
+    #[schema(value_type = Object)]
+    public_key_credential: PublicKeyCredential,
+}
+
+#[utoipa::path(
+    params(("Idempotency-Key" = Option<Uuid>, Header, description = "Replays the stored response when a request is retried")),
+    responses(
+        (status = 200, description = "Authenticated"),
+        (status = 401, description = "Assertion could not be verified", body = ServiceError),
+        (status = 404, description = "Passkey or authentication does not exist", body = ServiceError),
+        (status = 408, description = "Challenge has expired", body = ServiceError),
+        (status = 423, description = "Account is suspended", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[post("/finish-discoverable-authentication", wrap = "IdempotencyMiddleware")]
+async fn finish_discoverable_authentication(
+    request: HttpRequest,
+    authentication: web::Json<FinishDiscoverableAuthentication>,
+    pool: web::ThinData<PgPool>,
+    webauthn: web::Data<Webauthn>,
+    discoverable_store: web::Data<ChallengeStore<DiscoverableAuthentication>>,
+) -> impl Responder {
+    let credential = &authentication.public_key_credential;
+    let Some(challenge_key) = echoed_challenge_key(credential) else {
+        return ServiceError {
+            kind: ErrorKind::ValidationError,
+            message: "Client data does not contain a challenge".into(),
+        }
+        .into_response();
+    };
+    let (user_id, credential_id) = match webauthn.identify_discoverable_authentication(credential) {
+        Ok(result) => result,
+        Err(err) => return ServiceError::passkey_authentication_failure(&err),
+    };
+
+    let passkeys = match PasskeyRepository::get_user_credentials(&pool, &user_id).await {
+        Ok(passkeys) => passkeys,
+        Err(_) => return ServiceError::internal_server_error(),
+    };
+    let Some(passkey) = passkeys
+        .iter()
+        .find(|passkey| passkey.cred_id().as_slice() == credential_id)
+        .cloned()
+    else {
+        return ServiceError {
+            kind: ErrorKind::DoesNotExist,
+            message: "Passkey does not exist".into(),
+        }
+        .into_response();
+    };
+
+    let locked = match PasskeyRepository::get_user_by_id(&pool, &user_id).await {
+        Ok(Some(user)) => Repository::is_locked_by_mail(&pool, user.mail()).await,
+        Ok(None) => Ok(false),
+        Err(err) => Err(err),
+    };
+    match locked {
+        Ok(true) => return ServiceError::account_suspended(),
+        Ok(false) => {}
+        Err(_) => return ServiceError::internal_server_error(),
+    }
+
+    let discoverable_authentication = match discoverable_store.take(&challenge_key) {
+        Challenge::Valid(discoverable_authentication) => discoverable_authentication,
+        Challenge::Expired => return ServiceError::challenge_expired(),
+        Challenge::Missing => {
+            return ServiceError {
+                kind: ErrorKind::DoesNotExist,
+                message: "Passkey authentication does not exist".into(),
+            }
+            .into_response();
+        }
+    };
+
+    let discoverable_keys: Vec<DiscoverableKey> =
+        passkeys.iter().map(DiscoverableKey::from).collect();
+    let result = match webauthn.finish_discoverable_authentication(
+        credential,
+        discoverable_authentication,
+        &discoverable_keys,
+    ) {
+        Ok(result) => result,
+        Err(err) => {
+            log_audit_event(
+                &pool,
+                &request,
+                AuditAction::PasskeySignInFailure,
+                None,
+                json!({ "passkey_user_id": user_id }),
+            );
+            return ServiceError::passkey_authentication_failure(&err);
+        }
+    };
+    if store_updated_credential(&pool, passkey, &result)
+        .await
+        .is_err()
+    {
+        return ServiceError::internal_server_error();
+    }
+
+    log_audit_event(
+        &pool,
+        &request,
+        AuditAction::PasskeySignInSuccess,
+        None,
+        json!({ "passkey_user_id": user_id }),
+    );
+    HttpResponse::Ok().finish()
+}
+
+/// Deletes one of the user's passkeys. Without sessions the caller proves
+/// ownership with a fresh assertion: it starts an authentication for the
+/// user and sends the result here instead of to `/finish-authentication`.
+#[utoipa::path(
+    request_body = FinishPasskeyAuthentication,
+    responses(
+        (status = 204, description = "Passkey deleted"),
+        (status = 400, description = "Credential id is not hex", body = ServiceError),
+        (status = 401, description = "Assertion could not be verified", body = ServiceError),
+        (status = 404, description = "User, authentication or passkey does not exist", body = ServiceError),
+        (status = 408, description = "Challenge has expired", body = ServiceError),
+        (status = 409, description = "Last credential of the user", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[delete("/{credential_id}")]
+async fn delete_passkey(
+    request: HttpRequest,
+    credential_id: web::Path<String>,
+    authentication: web::Json<FinishPasskeyAuthentication>,
+    pool: web::ThinData<PgPool>,
+    webauthn: web::Data<Webauthn>,
+    authentication_store: web::Data<ChallengeStore<PendingPasskeyAuthentication>>,
+) -> impl Responder {
+    let Ok(credential_id) = hex::decode(credential_id.as_str()) else {
+        return ServiceError {
+            kind: ErrorKind::ValidationError,
+            message: "Credential id must be hex encoded".into(),
+        }
+        .into_response();
+    };
+
+    if let Err(response) = verify_assertion(
+        &request,
+        &pool,
+        &webauthn,
+        &authentication_store,
+        &authentication,
+    )
+    .await
+    {
+        return response;
+    }
+
+    let user = match PasskeyRepository::get_user_by_id(&pool, &authentication.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return ServiceError {
+                kind: ErrorKind::DoesNotExist,
+                message: "User does not exist".into(),
+            }
+            .into_response();
+        }
+        Err(_) => return ServiceError::internal_server_error(),
+    };
+
+    match PasskeyRepository::get_credential_by_id(&pool, &credential_id).await {
+        Ok(Some(credential)) if credential.user_id == *user.id() => {}
+        Ok(_) => {
+            return ServiceError {
+                kind: ErrorKind::DoesNotExist,
+                message: "Passkey does not exist".into(),
+            }
+            .into_response();
+        }
+        Err(_) => return ServiceError::internal_server_error(),
+    }
+
+    let (credential_count, password_user) = match tokio::try_join!(
+        PasskeyRepository::count_credentials(&pool, user.id()),
+        Repository::get_by_mail(&pool, user.mail()),
+    ) {
+        Ok(result) => result,
+        Err(_) => return ServiceError::internal_server_error(),
+    };
+
+    if credential_count <= 1 && password_user.is_none() {
+        return ServiceError {
+            kind: ErrorKind::LastCredential,
+            message: "Cannot delete the last passkey of a user without a password".into(),
+        }
+        .into_response();
+    }
+
+    match PasskeyRepository::delete_credential(&pool, &credential_id, user.id()).await {
+        Ok(true) => {
+            log_audit_event(
+                &pool,
+                &request,
+                AuditAction::PasskeyDeleted,
+                None,
+                json!({ "passkey_user_id": user.id(), "credential_id": hex::encode(&credential_id) }),
+            );
+            HttpResponse::NoContent().finish()
+        }
+        Ok(false) => ServiceError {
+            kind: ErrorKind::DoesNotExist,
+            message: "Passkey does not exist".into(),
+        }
+        .into_response(),
+        Err(err) => {
+            log!(Level::Error, "Credential deletion: {err}");
+            ServiceError::internal_server_error()
+        }
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    start_passkey_registration,
+    finish_passkey_registration,
+    start_passkey_authentication,
+    finish_passkey_authentication,
+    start_discoverable_authentication,
+    finish_discoverable_authentication,
+    delete_passkey,
+))]
+pub(super) struct PasskeyApi;
+
+pub(super) fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(start_passkey_registration)
+        .service(finish_passkey_registration)
+        .service(start_passkey_authentication)
+        .service(finish_passkey_authentication)
+        .service(start_discoverable_authentication)
+        .service(finish_discoverable_authentication)
+        .service(delete_passkey);
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_start_registration_optional_fields() {
+        let registration: StartPasskeyRegistration =
+            serde_json::from_value(json!({ "mail": "ada@example.com", "name": "Ada" })).unwrap();
+        assert_eq!(registration.hints, None);
+        assert_eq!(registration.authenticator_attachment, None);
+        assert_eq!(registration.resident_key, None);
+
+        let registration: StartPasskeyRegistration = serde_json::from_value(json!({
+            "mail": "ada@example.com",
+            "name": "Ada",
+            "hints": ["security-key"],
+            "authenticator_attachment": "platform",
+        }))
+        .unwrap();
+        assert_eq!(registration.hints, Some(vec!["security-key".to_string()]));
+        assert_eq!(
+            registration.authenticator_attachment.as_deref(),
+            Some("platform")
+        );
+
+        let unknown = json!({ "mail": "ada@example.com", "name": "Ada", "user_id": Uuid::nil() });
+        assert!(serde_json::from_value::<StartPasskeyRegistration>(unknown).is_err());
+        let authentication: StartPasskeyAuthentication =
+            serde_json::from_value(json!({ "user_id": Uuid::nil() })).unwrap();
+        assert_eq!(authentication.mail, None);
+        let unknown = json!({ "mail": "ada@example.com", "name": "Ada" });
+        assert!(serde_json::from_value::<StartPasskeyAuthentication>(unknown).is_err());
+    }
+
+    #[test]
+    fn test_finish_requests_reject_unknown_fields() {
+        let credential = json!({
+            "id": "AQID",
+            "rawId": "AQID",
+            "type": "public-key",
+            "response": { "attestationObject": "", "clientDataJSON": "" },
+        });
+        let finish = json!({
+            "user_id": Uuid::nil(),
+            "session_token": "00",
+            "register_public_key_credential": credential,
+        });
+        let registration: FinishPasskeyRegistration =
+            serde_json::from_value(finish.clone()).unwrap();
+        assert_eq!(registration.user_id, Uuid::nil());
+        assert_eq!(registration.session_token, "00");
+
+        let mut unknown = finish;
+        unknown["new_user"] = json!(true);
+        assert!(serde_json::from_value::<FinishPasskeyRegistration>(unknown).is_err());
+        let unknown = json!({ "public_key_credential": {}, "user_id": Uuid::nil() });
+        assert!(serde_json::from_value::<FinishDiscoverableAuthentication>(unknown).is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use actix_web::{HttpRequest, HttpResponse, Responder, post, web};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+use crate::{
+    config::{AccountPolicy, AppConfiguration},
+    crypto::{Method, PasswordHandler, PasswordHandlerTrait, PasswordStrengthConfig},
+    error::Error,
+    repository::{AuditAction, PasskeyRepository, Repository, User, UserDTO},
+    validation::validate_email,
+};
+
+use log::{Level, log};
+
+use super::{ErrorKind, FieldError, ServiceError, log_audit_event};
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct SignUpRequest {
+    name: String,
+    password: String,
+    mail: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SignUpResponse {
+    user_id: i64,
+}
+
+#[utoipa::path(
+    responses(
+        (status = 201, description = "Account created", body = SignUpResponse),
+        (status = 400, description = "Mail is not a valid address or password is too weak", body = FieldError),
+        (status = 403, description = "Mail domain is not allowed", body = ServiceError),
+        (status = 409, description = "User already exists", body = ServiceError),
+        (status = 503, description = "Registration is disabled", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[post("/sign-up")]
+async fn sign_up(
+    request: HttpRequest,
+    user: web::Json<SignUpRequest>,
+    pool: web::ThinData<PgPool>,
+    handler: web::Data<dyn PasswordHandlerTrait>,
+    app_config: web::ThinData<Arc<AppConfiguration>>,
+    account_policy: web::Data<AccountPolicy>,
+    password_strength: web::Data<PasswordStrengthConfig>,
+) -> impl Responder {
+    if !app_config.registration_enabled {
+        return ServiceError::registration_disabled();
+    }
+    if let Err(err) = validate_email(&user.mail) {
+        return ServiceError::invalid_field("mail", err.to_string());
+    }
+    if !account_policy.is_mail_allowed(&user.mail) {
+        return ServiceError::domain_not_allowed();
+    }
+    if let Err(violations) = PasswordHandler::is_strong(&user.password, &password_strength) {
+        return ServiceError::weak_password(violations);
+    }
+
+    let result = Repository::create_user_in_transaction(
+        &pool,
+        UserDTO::new(&user.mail, &user.name, &user.password, handler.as_ref()),
+    )
+    .await;
+
+    match result {
+        Ok(user_id) => {
+            log_audit_event(
+                &pool,
+                &request,
+                AuditAction::SignUp,
+                Some(user_id),
+                json!({}),
+            );
+            HttpResponse::Created().json(SignUpResponse { user_id })
+        }
+        Err(Error::UniqueViolation) => ServiceError {
+            kind: ErrorKind::AlreadyExists,
+            message: "User already exists".into(),
+        }
+        .into_response(),
+        Err(err) => ServiceError::from(err).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+struct SignInRequest {
+    mail: String,
+    password: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SignInResponse {
+    passkeys_registered: bool,
+}
+
+const MAX_FAILED_SIGN_INS: usize = 10;
+const FAILED_SIGN_IN_WINDOW: Duration = Duration::from_secs(600);
+/// Above this many tracked mail addresses, addresses without a recent
+/// failure are dropped before a new one is added.
+const MAX_TRACKED_MAILS: usize = 100_000;
+
+type MailHash = [u8; 32];
+
+/// Recent failed sign-ins per mail address, so that guesses spread over many
+/// client addresses still run into a limit. Addresses are only kept as
+/// SHA-256 hashes.
+#[derive(Default)]
+pub struct FailedSignIns(DashMap<MailHash, VecDeque<Instant>>);
+
+impl FailedSignIns {
+    pub(super) fn key(mail: &str) -> MailHash {
+        Sha256::digest(mail.trim().to_lowercase()).into()
+    }
+
+    /// Once the limit is reached, how long until the oldest failure leaves
+    /// the window.
+    pub(super) fn retry_after(&self, key: &MailHash) -> Option<Duration> {
+        let failures = self.0.get(key)?;
+        let mut recent = failures
+            .iter()
+            .filter(|failed_at| failed_at.elapsed() < FAILED_SIGN_IN_WINDOW);
+        let oldest = *recent.next()?;
+        (recent.count() + 1 >= MAX_FAILED_SIGN_INS)
+            .then(|| FAILED_SIGN_IN_WINDOW.saturating_sub(oldest.elapsed()))
+    }
+
+    pub(super) fn record_failure(&self, key: MailHash) {
+        if !self.0.contains_key(&key) && self.0.len() >= MAX_TRACKED_MAILS {
+            self.0.retain(|_, failures| {
+                failures
+                    .back()
+                    .is_some_and(|failed_at| failed_at.elapsed() < FAILED_SIGN_IN_WINDOW)
+            });
+        }
+
+        let mut failures = self.0.entry(key).or_default();
+        while failures
+            .front()
+            .is_some_and(|failed_at| failed_at.elapsed() >= FAILED_SIGN_IN_WINDOW)
+        {
+            failures.pop_front();
+        }
+        failures.push_back(Instant::now());
+    }
+
+    pub(super) fn reset(&self, key: &MailHash) {
+        self.0.remove(key);
+    }
+}
+
+/// Checks `password` against the stored hash of `user`, with the method that
+/// produced it. A hash the handler upgrades on the way is stored; failing to
+/// store it does not fail the check.
+pub(super) async fn verify_password(
+    pool: &PgPool,
+    handler: &dyn PasswordHandlerTrait,
+    user: &User,
+    password: &str,
+) -> bool {
+    let method = PasswordHandler::detect_method(user.password_hash()).unwrap_or(Method::SaltPepper);
+    let verification = handler.verify(password, user.password_hash(), method);
+    if let Some(password_hash) = verification.updated_hash
+        && let Err(err) = Repository::update_password_hash(pool, user.id(), &password_hash).await
+    {
+        log!(Level::Error, "Password rehash: {err}");
+    }
+    verification.matches
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Signed in", body = SignInResponse),
+        (status = 401, description = "Wrong mail or password, or the account only has passkeys", body = ServiceError),
+        (status = 423, description = "Account is suspended", body = ServiceError),
+        (status = 429, description = "Too many failed sign-ins for this mail address", body = ServiceError),
+        (status = 500, description = "Unexpected error", body = ServiceError),
+    )
+)]
+#[post("/sign-in")]
+async fn sign_in(
+    request: HttpRequest,
+    user: web::Json<SignInRequest>,
+    pool: web::ThinData<PgPool>,
+    handler: web::Data<dyn PasswordHandlerTrait>,
+    failed_sign_ins: web::Data<FailedSignIns>,
+) -> impl Responder {
+    let mail_hash = FailedSignIns::key(&user.mail);
+    if let Some(retry_after) = failed_sign_ins.retry_after(&mail_hash) {
+        log_audit_event(
+            &pool,
+            &request,
+            AuditAction::RateLimitExceededEmail,
+            None,
+            json!({ "mail_hash": hex::encode(mail_hash) }),
+        );
+        return ServiceError::rate_limited(retry_after);
+    }
+
+    let result = tokio::try_join!(
+        Repository::get_by_mail(&pool, &user.mail),
+        PasskeyRepository::get_user_by_mail(&pool, &user.mail),
+    );
+
+    match result {
+        Ok((Some(user_details), _)) => {
+            if verify_password(&pool, handler.as_ref(), &user_details, &user.password).await {
+                failed_sign_ins.reset(&mail_hash);
+                // Only reported after the password matched, so the lock state is
+                // no oracle and locked accounts still count failed guesses.
+                if !user_details.is_active() {
+                    return ServiceError::account_suspended();
+                }
+                log_audit_event(
+                    &pool,
+                    &request,
+                    AuditAction::SignInSuccess,
+                    Some(user_details.id()),
+                    json!({}),
+                );
+                match PasskeyRepository::has_credentials(&pool, &user.mail).await {
+                    Ok(passkeys_registered) => HttpResponse::Ok().json(SignInResponse {
+                        passkeys_registered,
+                    }),
+                    Err(_) => ServiceError::internal_server_error(),
+                }
+            } else {
+                failed_sign_ins.record_failure(mail_hash);
+                log_audit_event(
+                    &pool,
+                    &request,
+                    AuditAction::SignInFailure,
+                    Some(user_details.id()),
+                    json!({}),
+                );
+                ServiceError {
+                    kind: ErrorKind::AuthenticationFailure,
+                    message: "Failed to authenticate".into(),
+                }
+                .into_response()
+            }
+        }
+        Ok((None, passkey_user)) => {
+            // Verify against a dummy hash so unknown mails take as long as wrong passwords.
+            let _ = handler.verify(&user.password, handler.dummy_hash(), handler.hash_method());
+            failed_sign_ins.record_failure(mail_hash);
+            log_audit_event(
+                &pool,
+                &request,
+                AuditAction::SignInFailure,
+                None,
+                json!({ "mail_hash": hex::encode(mail_hash) }),
+            );
+            if passkey_user.is_some() {
+                return ServiceError {
+                    kind: ErrorKind::PasskeyOnly,
+                    message: "This account uses passkey authentication only. Please use the passkey flow.".into(),
+                }
+                .into_response();
+            }
+            ServiceError {
+                kind: ErrorKind::AuthenticationFailure,
+                message: "Failed to authenticate".into(),
+            }
+            .into_response()
+        }
+        Err(_) => ServiceError::internal_server_error(),
+    }
+}
+
+pub(super) fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(sign_up).service(sign_in);
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    #[test]
+    fn test_sign_up_request_fields() {
+        let request: SignUpRequest = serde_json::from_value(
+            json!({ "name": "Ada", "mail": "ada@example.com", "password": "correct horse" }),
+        )
+        .unwrap();
+        assert_eq!(
+            (request.name.as_str(), request.mail.as_str()),
+            ("Ada", "ada@example.com")
+        );
+        assert_eq!(request.password, "correct horse");
+
+        let missing = json!({ "name": "Ada", "mail": "ada@example.com" });
+        assert!(serde_json::from_value::<SignUpRequest>(missing).is_err());
+        let unknown =
+            json!({ "name": "Ada", "mail": "a@example.com", "password": "x", "role": "admin" });
+        assert!(serde_json::from_value::<SignUpRequest>(unknown).is_err());
+        let unknown = json!({ "mail": "a@example.com", "password": "x", "remember": true });
+        assert!(serde_json::from_value::<SignInRequest>(unknown).is_err());
+    }
+
+    #[test]
+    fn test_response_json_shape() {
+        let response = serde_json::to_value(SignUpResponse { user_id: 7 }).unwrap();
+        assert_eq!(response, json!({ "user_id": 7 }));
+        let response = serde_json::to_value(SignInResponse {
+            passkeys_registered: true,
+        })
+        .unwrap();
+        assert_eq!(response["passkeys_registered"], Value::Bool(true));
+    }
+}
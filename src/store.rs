@@ -0,0 +1,154 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use webauthn_rs::prelude::Uuid;
+
+/// Pending WebAuthn challenges keyed by user id. Entries live in a sharded
+/// map, so concurrent ceremonies for different users do not contend on a
+/// single lock.
+pub struct ChallengeStore<V> {
+    challenges: DashMap<Uuid, (V, Instant)>,
+    /// Number of stored entries, kept separately because `DashMap::len`
+    /// has to lock every shard.
+    len: AtomicUsize,
+    timeout: Duration,
+    capacity: usize,
+}
+
+pub enum Insertion {
+    Stored,
+    Full,
+}
+
+pub enum Challenge<V> {
+    Valid(V),
+    Expired,
+    Missing,
+}
+
+impl<V> ChallengeStore<V> {
+    pub fn new(timeout: Duration, capacity: usize) -> Self {
+        Self {
+            challenges: DashMap::new(),
+            len: AtomicUsize::new(0),
+            timeout,
+            capacity,
+        }
+    }
+
+    pub fn insert(&self, key: Uuid, value: V) -> Insertion {
+        if !self.reserve() {
+            self.purge_expired();
+            if !self.reserve() {
+                return Insertion::Full;
+            }
+        }
+
+        if self
+            .challenges
+            .insert(key, (value, Instant::now()))
+            .is_some()
+        {
+            self.len.fetch_sub(1, Ordering::AcqRel);
+        }
+
+        Insertion::Stored
+    }
+
+    /// Entries past their timeout that have not been purged yet.
+    pub fn count_expired(&self) -> usize {
+        self.challenges
+            .iter()
+            .filter(|entry| entry.value().1.elapsed() > self.timeout)
+            .count()
+    }
+
+    pub fn purge_expired(&self) -> usize {
+        let mut purged = 0;
+        self.challenges.retain(|_, (_, created_at)| {
+            let valid = created_at.elapsed() <= self.timeout;
+            if !valid {
+                purged += 1;
+            }
+            valid
+        });
+        self.len.fetch_sub(purged, Ordering::AcqRel);
+
+        purged
+    }
+
+    /// Drops every entry, valid or not, and returns how many there were.
+    pub fn clear(&self) -> usize {
+        let mut cleared = 0;
+        self.challenges.retain(|_, _| {
+            cleared += 1;
+            false
+        });
+        self.len.fetch_sub(cleared, Ordering::AcqRel);
+
+        cleared
+    }
+
+    pub fn take(&self, key: &Uuid) -> Challenge<V> {
+        self.take_if(key, |_| true)
+    }
+
+    /// Removes the entry only if `matches` accepts it. A rejected entry stays
+    /// in place and is reported as missing, so a caller that does not own a
+    /// challenge can neither claim nor discard it.
+    pub fn take_if(&self, key: &Uuid, matches: impl FnOnce(&V) -> bool) -> Challenge<V> {
+        match self
+            .challenges
+            .remove_if(key, |_, (value, _)| matches(value))
+        {
+            Some((_, (value, created_at))) => {
+                self.len.fetch_sub(1, Ordering::AcqRel);
+                if created_at.elapsed() <= self.timeout {
+                    Challenge::Valid(value)
+                } else {
+                    Challenge::Expired
+                }
+            }
+            None => Challenge::Missing,
+        }
+    }
+
+    /// Claims a slot for a new entry, failing once the store is at capacity.
+    fn reserve(&self) -> bool {
+        self.len
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |len| {
+                (len < self.capacity).then_some(len + 1)
+            })
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capacity_frees_on_take_and_expiry() {
+        let store = ChallengeStore::new(Duration::from_millis(20), 2);
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        assert!(matches!(store.insert(first, 1), Insertion::Stored));
+        assert!(matches!(store.insert(second, 2), Insertion::Stored));
+        assert!(matches!(store.insert(first, 3), Insertion::Full));
+
+        assert!(matches!(
+            store.take_if(&first, |&value| value == 2),
+            Challenge::Missing
+        ));
+        assert!(matches!(store.take(&first), Challenge::Valid(1)));
+        assert!(matches!(store.take(&first), Challenge::Missing));
+        assert!(matches!(store.insert(first, 3), Insertion::Stored));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(matches!(store.insert(Uuid::new_v4(), 4), Insertion::Stored));
+        assert_eq!(store.len.load(Ordering::Acquire), 1);
+    }
+}
@@ -0,0 +1,37 @@
+//! Helpers shared by the tests of several modules.
+
+use sqlx::PgPool;
+
+pub use crate::config::Configuration;
+use crate::{
+    config::AppConfiguration,
+    crypto::{PasswordHandler, PasswordHandlerTrait},
+    repository::{Repository, UserDTO},
+};
+
+pub fn test_app_config() -> AppConfiguration {
+    Configuration::from_test_defaults().app_config().clone()
+}
+
+/// A real password handler using the pepper of [`test_app_config`].
+pub fn test_password_handler() -> PasswordHandler {
+    let app_config = test_app_config();
+    PasswordHandler::new(
+        10,
+        app_config.pepper.clone(),
+        app_config.pepper_history(),
+        app_config.hash_algorithm().unwrap(),
+    )
+}
+
+/// Creates a password account named "Test User" and returns its id.
+pub async fn create_test_user(
+    pool: &PgPool,
+    handler: &dyn PasswordHandlerTrait,
+    mail: &str,
+    password: &str,
+) -> i64 {
+    Repository::create_user_in_transaction(pool, UserDTO::new(mail, "Test User", password, handler))
+        .await
+        .expect("Test user must be created")
+}
@@ -0,0 +1,130 @@
+use std::{
+    cell::Cell,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::crypto::{Method, PasswordHandlerTrait, VerifyResult};
+
+tokio::task_local! {
+    static TIMINGS: Timings;
+}
+
+/// Time a request spent in the database and in password hashing. Calls
+/// running concurrently within one request are added up, so the parts can
+/// exceed the total.
+#[derive(Debug, Default)]
+pub struct Timings {
+    db: Cell<Duration>,
+    hash: Cell<Duration>,
+}
+
+impl Timings {
+    pub fn db(&self) -> Duration {
+        self.db.get()
+    }
+
+    pub fn hash(&self) -> Duration {
+        self.hash.get()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Metric {
+    Db,
+    Hash,
+}
+
+/// Runs `future` while collecting [`Timings`] for the [`Timer`]s dropped
+/// inside it.
+pub async fn collect<F: Future>(future: F) -> (F::Output, Timings) {
+    TIMINGS
+        .scope(Timings::default(), async move {
+            let output = future.await;
+            let timings = TIMINGS.with(|timings| Timings {
+                db: Cell::new(timings.db()),
+                hash: Cell::new(timings.hash()),
+            });
+            (output, timings)
+        })
+        .await
+}
+
+/// Adds the time until it is dropped to the current request's [`Timings`].
+/// Outside of [`collect`], e.g. in spawned tasks, it records nothing.
+pub struct Timer {
+    metric: Metric,
+    started_at: Instant,
+}
+
+impl Timer {
+    pub fn start(metric: Metric) -> Self {
+        Self {
+            metric,
+            started_at: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let elapsed = self.started_at.elapsed();
+        let _ = TIMINGS.try_with(|timings| {
+            let total = match self.metric {
+                Metric::Db => &timings.db,
+                Metric::Hash => &timings.hash,
+            };
+            total.set(total.get() + elapsed);
+        });
+    }
+}
+
+/// Records the time spent in the wrapped handler as [`Metric::Hash`].
+pub struct TimedPasswordHandler(pub Arc<dyn PasswordHandlerTrait>);
+
+impl PasswordHandlerTrait for TimedPasswordHandler {
+    fn hash(&self, value: &str, method: Method) -> String {
+        let _timer = Timer::start(Metric::Hash);
+        self.0.hash(value, method)
+    }
+
+    fn is_hash_of(&self, value: &str, original_hash: &str, method: Method) -> bool {
+        let _timer = Timer::start(Metric::Hash);
+        self.0.is_hash_of(value, original_hash, method)
+    }
+
+    fn verify(&self, value: &str, original_hash: &str, method: Method) -> VerifyResult {
+        let _timer = Timer::start(Metric::Hash);
+        self.0.verify(value, original_hash, method)
+    }
+
+    fn dummy_hash(&self) -> &str {
+        self.0.dummy_hash()
+    }
+
+    fn hash_method(&self) -> Method {
+        self.0.hash_method()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_timers_add_up_inside_collect() {
+        let ((), timings) = collect(async {
+            let _timer = Timer::start(Metric::Db);
+            sleep(Duration::from_millis(5));
+        })
+        .await;
+        assert!(timings.db() >= Duration::from_millis(5));
+        assert_eq!(timings.hash(), Duration::ZERO);
+
+        // Outside of `collect` a timer is a no-op.
+        drop(Timer::start(Metric::Hash));
+    }
+}
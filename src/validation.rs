@@ -0,0 +1,73 @@
+use email_address::EmailAddress;
+
+use crate::error::Error;
+
+/// Checks that `mail` is an RFC 5322 address before it is stored, so later
+/// mail delivery and display do not have to deal with malformed input.
+pub fn validate_email(mail: &str) -> Result<(), Error> {
+    if EmailAddress::is_valid(mail) {
+        Ok(())
+    } else {
+        Err(Error::Other("Invalid email format".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_email() {
+        let valid = [
+            "user@example.com",
+            "first.last@example.com",
+            "user+tag@example.com",
+            "user-name@example.co.uk",
+            "user_name@example.org",
+            "USER@EXAMPLE.COM",
+            "x@example.com",
+            "1234567890@example.com",
+            "user@sub.domain.example.com",
+            "user@example-domain.com",
+            "o'brien@example.com",
+            "user%percent@example.com",
+            "#!$%&*+-/=?^_`{}|~@example.org",
+            "\"quoted name\"@example.com",
+            "\"very.(),:;<>[]\\\".unusual\"@example.com",
+            "user@[192.168.0.1]",
+            "user@[IPv6:2001:db8::1]",
+            "user@localhost",
+            "a.b.c.d@example.com",
+            "user@xn--bcher-kva.example",
+        ];
+        let invalid = [
+            "",
+            "plainaddress",
+            "@example.com",
+            "user@",
+            "user@@example.com",
+            "user@exa mple.com",
+            "user name@example.com",
+            ".user@example.com",
+            "user.@example.com",
+            "us..er@example.com",
+            "user@example..com",
+            "user@.example.com",
+            "user@example.com.",
+            "user@-example.com",
+            "user@example-.com",
+            "user(comment)example.com",
+            "user@exam<ple.com",
+            "a\"b(c)d,e:f;g<h>i[j\\k]l@example.com",
+            "user@[192.168.0.1",
+            "user\n@example.com",
+        ];
+
+        for mail in valid {
+            assert!(validate_email(mail).is_ok(), "{mail} should be valid");
+        }
+        for mail in invalid {
+            assert!(validate_email(mail).is_err(), "{mail} should be invalid");
+        }
+    }
+}